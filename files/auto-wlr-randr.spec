@@ -22,8 +22,6 @@ BuildRequires:  systemd-rpm-macros
 BuildRequires:  wayland-devel
 BuildRequires:  pandoc
 
-Requires:       wlr-randr
-
 # prevent having both
 %if 0%{?git_build}
 Conflicts:      auto-wlr-randr
@@ -0,0 +1,129 @@
+use crate::backend::{OutputBackend, plan_configuration};
+use crate::config::{OutputSetting, Profile};
+use crate::output::OutputInfo;
+use anyhow::Result;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub struct AppliedProfile {
+    pub profile: Profile,
+    pub name_map: HashMap<String, String>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    outputs: Vec<OutputInfo>,
+    applied: Vec<AppliedProfile>,
+    fail_apply: bool,
+    fail_test: bool,
+    rejected_outputs: Vec<String>,
+    ignored_outputs: Vec<String>,
+    changed: bool,
+}
+
+/// In-memory backend for running the daemon logic without a compositor.
+///
+/// Clones share their state, so a test can keep a handle after giving one to `WaylandState`.
+#[derive(Debug, Clone, Default)]
+pub struct MockBackend {
+    state: Rc<RefCell<MockState>>,
+}
+
+impl MockBackend {
+    pub fn new(outputs: Vec<OutputInfo>) -> Self {
+        let backend = Self::default();
+        backend.set_outputs(outputs);
+        backend
+    }
+
+    /// Simulates a hotplug. Like applies, reported by `has_changes` until the outputs
+    /// are queried.
+    pub fn set_outputs(&self, outputs: Vec<OutputInfo>) {
+        let mut state = self.state.borrow_mut();
+        state.outputs = outputs;
+        state.changed = true;
+    }
+
+    pub fn connected_outputs(&self) -> Vec<OutputInfo> {
+        self.state.borrow().outputs.clone()
+    }
+
+    /// profiles applied so far, oldest first
+    pub fn applied(&self) -> Vec<AppliedProfile> {
+        self.state.borrow().applied.clone()
+    }
+
+    /// makes every following apply fail as if the compositor rejected it
+    pub fn set_fail_apply(&self, fail: bool) {
+        self.state.borrow_mut().fail_apply = fail;
+    }
+
+    /// makes every following test fail as if the compositor rejected it
+    pub fn set_fail_test(&self, fail: bool) {
+        self.state.borrow_mut().fail_test = fail;
+    }
+
+    /// Settings for `outputs` make an apply fail after the other outputs were already
+    /// changed, like wlr-randr failing half-way.
+    pub fn set_rejected_outputs(&self, outputs: &[&str]) {
+        self.state.borrow_mut().rejected_outputs = outputs.iter().map(|o| o.to_string()).collect();
+    }
+
+    /// settings for `outputs` are dropped without the apply failing
+    pub fn set_ignored_outputs(&self, outputs: &[&str]) {
+        self.state.borrow_mut().ignored_outputs = outputs.iter().map(|o| o.to_string()).collect();
+    }
+}
+
+impl OutputBackend for MockBackend {
+    fn outputs(&mut self) -> Result<Vec<OutputInfo>> {
+        self.state.borrow_mut().changed = false;
+        Ok(self.connected_outputs())
+    }
+
+    fn apply(&mut self, profile: &Profile, name_map: &HashMap<String, String>) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        if state.fail_apply {
+            anyhow::bail!("Mock backend rejected the configuration");
+        }
+
+        let output_name = |s: &OutputSetting| name_map.get(&s.output).unwrap_or(&s.output).clone();
+        let (skipped, settings): (Vec<_>, Vec<_>) =
+            profile.settings.iter().cloned().partition(|s| {
+                let name = output_name(s);
+                state.rejected_outputs.contains(&name) || state.ignored_outputs.contains(&name)
+            });
+
+        state.outputs = plan_configuration(&state.outputs, &settings, name_map)?;
+        state.changed = true;
+        state.applied.push(AppliedProfile {
+            profile: profile.clone(),
+            name_map: name_map.clone(),
+        });
+
+        let rejected: Vec<String> = skipped
+            .iter()
+            .map(output_name)
+            .filter(|name| state.rejected_outputs.contains(name))
+            .collect();
+        if !rejected.is_empty() {
+            anyhow::bail!("Mock backend rejected outputs {}", rejected.join(", "));
+        }
+        Ok(())
+    }
+
+    fn test(&mut self, profile: &Profile, name_map: &HashMap<String, String>) -> Result<()> {
+        let state = self.state.borrow();
+        if state.fail_test {
+            anyhow::bail!("Mock backend rejected the configuration");
+        }
+
+        plan_configuration(&state.outputs, &profile.settings, name_map).map(|_| ())
+    }
+
+    fn has_changes(&mut self) -> Result<bool> {
+        Ok(std::mem::take(&mut self.state.borrow_mut().changed))
+    }
+}
@@ -0,0 +1,615 @@
+pub mod mock;
+pub mod native;
+pub mod wlr_randr;
+
+use crate::config::{ModeList, OutputSetting, Profile, Scale};
+use crate::output::{Mode, ModeCandidate, OutputInfo, Position};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+pub use mock::MockBackend;
+pub use native::NativeBackend;
+pub use wlr_randr::WlrRandrBackend;
+
+/// Source of truth about connected outputs and the way profiles get applied to them.
+pub trait OutputBackend {
+    fn outputs(&mut self) -> Result<Vec<OutputInfo>>;
+
+    /// applies output settings of `profile`, `name_map` maps patterns to output names
+    fn apply(&mut self, profile: &Profile, name_map: &HashMap<String, String>) -> Result<()>;
+
+    /// asks the compositor whether it would accept `profile` without applying it
+    fn test(&mut self, profile: &Profile, name_map: &HashMap<String, String>) -> Result<()>;
+
+    /// Whether the compositor reported the outputs changing since they were last queried,
+    /// for backends that learn about changes on their own. Reports each change once.
+    fn has_changes(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackendKind {
+    /// talk wlr-output-management directly
+    #[default]
+    Native,
+    /// shell out to the wlr-randr binary
+    WlrRandr,
+}
+
+fn select_mode(output: &OutputInfo, setting: &OutputSetting) -> Result<Option<Mode>> {
+    if let Some(modes) = &setting.mode {
+        let candidates = modes
+            .0
+            .iter()
+            .map(|mode| mode.parse())
+            .collect::<Result<Vec<ModeCandidate>>>()?;
+        if let Some(found) = candidates.iter().find_map(|c| c.select(output)) {
+            return Ok(Some(found.clone()));
+        }
+
+        let custom = candidates.iter().find_map(|c| match c {
+            ModeCandidate::Exact(spec) => spec.refresh.map(|refresh| (spec, refresh)),
+            _ => None,
+        });
+        let Some((spec, refresh)) = custom else {
+            anyhow::bail!(
+                "Mode '{modes}' is not available on output '{}'",
+                output.name
+            );
+        };
+        log::warn!(
+            "No mode of output '{}' matches '{modes}', using custom mode",
+            output.name
+        );
+        return Ok(Some(Mode {
+            width: spec.width,
+            height: spec.height,
+            refresh,
+            preferred: false,
+            current: true,
+        }));
+    }
+
+    if setting.preferred || output.current_mode().is_none() {
+        return Ok(output.preferred_mode().cloned());
+    }
+
+    Ok(None)
+}
+
+/// Copy of `profile` where the `mode` and an automatic `scale` of every setting are
+/// replaced by the single mode and the scale they select on the connected outputs.
+/// Both are dropped for outputs the profile turns off, an automatic scale also for
+/// outputs that don't report their physical size.
+pub fn resolve_settings(
+    profile: &Profile,
+    outputs: &[OutputInfo],
+    name_map: &HashMap<String, String>,
+) -> Result<Profile> {
+    let mut profile = profile.clone();
+    let needs_resolving =
+        |s: &OutputSetting| s.mode.is_some() || matches!(s.scale, Some(Scale::Auto(_)));
+    if !profile.settings.iter().any(needs_resolving) {
+        return Ok(profile);
+    }
+
+    let planned = plan_configuration(outputs, &profile.settings, name_map)?;
+    for setting in &mut profile.settings {
+        let name = name_map.get(&setting.output).unwrap_or(&setting.output);
+        let output = planned
+            .iter()
+            .find(|o| &o.name == name)
+            .filter(|o| o.enabled);
+
+        if setting.mode.is_some() {
+            let mode = output.and_then(OutputInfo::current_mode);
+            setting.mode = mode.map(|m| ModeList::from(m.to_string().as_str()));
+        }
+        if let Some(Scale::Auto(_)) = setting.scale {
+            setting.scale = output
+                .filter(|o| o.dpi().is_some())
+                .map(|o| Scale::Fixed(o.scale as f32));
+        }
+    }
+
+    Ok(profile)
+}
+
+/// Computes the state every output should end up in after applying `settings`.
+///
+/// Relative placement is resolved against the already planned state, so it mirrors
+/// how wlr-randr processes its arguments in order.
+pub fn plan_configuration(
+    outputs: &[OutputInfo],
+    settings: &[OutputSetting],
+    name_map: &HashMap<String, String>,
+) -> Result<Vec<OutputInfo>> {
+    let mut planned = outputs.to_vec();
+
+    for setting in settings {
+        let output_name = name_map.get(&setting.output).unwrap_or(&setting.output);
+        let idx = planned
+            .iter()
+            .position(|o| &o.name == output_name)
+            .with_context(|| format!("Output '{output_name}' is not connected"))?;
+
+        let output = &mut planned[idx];
+        if let Some(on) = setting.on {
+            output.enabled = on;
+        }
+        if !output.enabled {
+            continue;
+        }
+
+        if let Some(mode) = select_mode(output, setting)? {
+            output.set_current_mode(&mode);
+        }
+        if let Some(transform) = &setting.transform {
+            output.transform = transform.parse()?;
+        }
+        match setting.scale {
+            Some(Scale::Fixed(scale)) => {
+                if scale <= 0.0 {
+                    anyhow::bail!("Invalid scale '{scale}' for output '{output_name}'");
+                }
+                output.scale = f64::from(scale);
+            }
+            Some(Scale::Auto(auto)) => match auto.scale_for(output) {
+                Some(scale) => output.scale = scale,
+                None => log::warn!(
+                    "Output '{output_name}' doesn't report its physical size, keeping its scale"
+                ),
+            },
+            None => {}
+        }
+        if let Some(adaptive_sync) = setting.adaptive_sync {
+            output.adaptive_sync = adaptive_sync;
+        }
+
+        if let Some(pos) = &setting.pos {
+            output.position = pos.parse()?;
+        }
+
+        let relative = [
+            (&setting.left_of, Placement::LeftOf),
+            (&setting.right_of, Placement::RightOf),
+            (&setting.above, Placement::Above),
+            (&setting.below, Placement::Below),
+        ];
+        for (target, placement) in relative {
+            let Some(target) = target else { continue };
+            let anchor = planned
+                .iter()
+                .find(|o| &o.name == target)
+                .with_context(|| format!("Relative position target '{target}' is not connected"))?;
+            let position = placement.place(anchor, planned[idx].logical_size());
+            planned[idx].position = position;
+        }
+    }
+
+    Ok(planned)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Placement {
+    LeftOf,
+    RightOf,
+    Above,
+    Below,
+}
+
+impl Placement {
+    fn place(self, anchor: &OutputInfo, (width, height): (i32, i32)) -> Position {
+        let (anchor_width, anchor_height) = anchor.logical_size();
+        let Position { x, y } = anchor.position;
+        match self {
+            Placement::LeftOf => Position { x: x - width, y },
+            Placement::RightOf => Position {
+                x: x + anchor_width,
+                y,
+            },
+            Placement::Above => Position { x, y: y - height },
+            Placement::Below => Position {
+                x,
+                y: y + anchor_height,
+            },
+        }
+    }
+}
+
+/// fields of the planned state of an output that `actual` doesn't match
+fn differences(expected: &OutputInfo, actual: &OutputInfo) -> Vec<&'static str> {
+    if !expected.enabled || !actual.enabled {
+        return if expected.enabled == actual.enabled {
+            Vec::new()
+        } else {
+            vec!["enabled"]
+        };
+    }
+
+    // compositors may round a custom refresh rate and store the scale as a fixed point
+    // number, so both are only compared roughly
+    let same_mode = match (expected.current_mode(), actual.current_mode()) {
+        (Some(e), Some(a)) => {
+            e.width == a.width && e.height == a.height && e.refresh.round() == a.refresh.round()
+        }
+        (e, a) => e.is_none() && a.is_none(),
+    };
+    let checks = [
+        ("mode", same_mode),
+        ("position", expected.position == actual.position),
+        ("transform", expected.transform == actual.transform),
+        ("scale", (expected.scale - actual.scale).abs() < 0.01),
+        (
+            "adaptive sync",
+            expected.adaptive_sync == actual.adaptive_sync,
+        ),
+    ];
+    checks
+        .into_iter()
+        .filter(|(_, same)| !same)
+        .map(|(field, _)| field)
+        .collect()
+}
+
+/// Checks that the outputs ended up in the `expected` state, outputs that went away
+/// in the meantime are ignored.
+pub fn verify(expected: &[OutputInfo], actual: &[OutputInfo]) -> Result<()> {
+    let mismatches: Vec<String> = expected
+        .iter()
+        .filter_map(|e| {
+            let a = actual.iter().find(|a| a.name == e.name)?;
+            // compositors accept adaptive sync for outputs that don't support it and
+            // just leave it off, that's not worth undoing the whole profile for
+            let mut fields = differences(e, a);
+            fields.retain(|field| *field != "adaptive sync");
+            (!fields.is_empty()).then(|| format!("{} ({})", e.name, fields.join(", ")))
+        })
+        .collect();
+
+    if !mismatches.is_empty() {
+        anyhow::bail!("Outputs differ from the profile: {}", mismatches.join("; "));
+    }
+    Ok(())
+}
+
+/// Profile bringing the outputs from their `current` state back to `snapshot`, with
+/// settings only for the outputs and fields that changed.
+pub fn restore_profile(snapshot: &[OutputInfo], current: &[OutputInfo]) -> Profile {
+    let settings = snapshot
+        .iter()
+        .filter_map(|old| {
+            let now = current.iter().find(|now| now.name == old.name)?;
+            let changed = differences(old, now);
+            if changed.is_empty() {
+                return None;
+            }
+
+            let mut setting = OutputSetting {
+                output: old.name.clone(),
+                on: Some(old.enabled),
+                ..Default::default()
+            };
+            if !old.enabled {
+                return Some(setting);
+            }
+
+            let restore_all = changed.contains(&"enabled");
+            let restore = |field| restore_all || changed.contains(&field);
+            if restore("mode") {
+                setting.mode = old
+                    .current_mode()
+                    .map(|m| ModeList::from(m.to_string().as_str()));
+            }
+            if restore("position") {
+                setting.pos = Some(format!("{},{}", old.position.x, old.position.y));
+            }
+            if restore("transform") {
+                setting.transform = Some(old.transform.to_string());
+            }
+            if restore("scale") {
+                setting.scale = Some(Scale::Fixed(old.scale as f32));
+            }
+            if restore("adaptive sync") {
+                setting.adaptive_sync = Some(old.adaptive_sync);
+            }
+            Some(setting)
+        })
+        .collect();
+
+    Profile {
+        settings,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AutoScale;
+    use crate::output::{PhysicalSize, Transform};
+
+    fn make_mode(width: i32, height: i32, refresh: f64, preferred: bool) -> Mode {
+        Mode {
+            width,
+            height,
+            refresh,
+            preferred,
+            current: false,
+        }
+    }
+
+    fn make_output(name: &str, enabled: bool) -> OutputInfo {
+        let mut output = OutputInfo {
+            name: name.to_string(),
+            enabled,
+            modes: vec![
+                make_mode(1920, 1080, 60.0, true),
+                make_mode(1920, 1080, 143.981, false),
+                make_mode(1280, 720, 60.0, false),
+            ],
+            ..Default::default()
+        };
+        if enabled {
+            output.modes[0].current = true;
+        }
+        output
+    }
+
+    fn make_setting(output: &str) -> OutputSetting {
+        OutputSetting {
+            output: output.into(),
+            alias: None,
+            on: Some(true),
+            mode: None,
+            preferred: false,
+            pos: None,
+            left_of: None,
+            right_of: None,
+            above: None,
+            below: None,
+            transform: None,
+            scale: None,
+            adaptive_sync: None,
+        }
+    }
+
+    #[test]
+    fn test_plan_mode_and_position() {
+        let outputs = vec![make_output("eDP-1", true), make_output("HDMI-A-1", false)];
+        let settings = vec![
+            OutputSetting {
+                pos: Some("0,0".into()),
+                ..make_setting("eDP-1")
+            },
+            OutputSetting {
+                mode: Some("1920x1080@143.981Hz".into()),
+                right_of: Some("eDP-1".into()),
+                ..make_setting("Dell*")
+            },
+        ];
+        let mut name_map = HashMap::new();
+        name_map.insert("Dell*".to_string(), "HDMI-A-1".to_string());
+
+        let planned = plan_configuration(&outputs, &settings, &name_map).unwrap();
+
+        assert!(planned[1].enabled);
+        assert_eq!(planned[1].current_mode().unwrap().refresh, 143.981);
+        assert_eq!(planned[1].position, Position { x: 1920, y: 0 });
+    }
+
+    #[test]
+    fn test_plan_relative_uses_scale_and_transform() {
+        let outputs = vec![make_output("eDP-1", true), make_output("DP-1", true)];
+        let settings = vec![
+            OutputSetting {
+                scale: Some(Scale::Fixed(2.0)),
+                transform: Some("90".into()),
+                ..make_setting("eDP-1")
+            },
+            OutputSetting {
+                left_of: Some("eDP-1".into()),
+                ..make_setting("DP-1")
+            },
+            OutputSetting {
+                below: Some("DP-1".into()),
+                ..make_setting("eDP-1")
+            },
+        ];
+
+        let planned = plan_configuration(&outputs, &settings, &HashMap::new()).unwrap();
+
+        assert_eq!(planned[0].logical_size(), (540, 960));
+        assert_eq!(planned[1].position, Position { x: -1920, y: 0 });
+        assert_eq!(planned[0].position, Position { x: -1920, y: 1080 });
+    }
+
+    #[test]
+    fn test_plan_disable_and_preferred() {
+        let outputs = vec![make_output("eDP-1", true), make_output("DP-1", false)];
+        let settings = vec![
+            OutputSetting {
+                on: Some(false),
+                ..make_setting("eDP-1")
+            },
+            make_setting("DP-1"),
+        ];
+
+        let planned = plan_configuration(&outputs, &settings, &HashMap::new()).unwrap();
+
+        assert!(!planned[0].enabled);
+        assert!(planned[1].enabled);
+        assert!(planned[1].current_mode().unwrap().preferred);
+    }
+
+    #[test]
+    fn test_plan_unset_leaves_state() {
+        let mut outputs = vec![make_output("eDP-1", true), make_output("DP-1", false)];
+        outputs[0].adaptive_sync = true;
+        let settings = vec![
+            OutputSetting {
+                on: None,
+                scale: Some(Scale::Fixed(2.0)),
+                ..make_setting("eDP-1")
+            },
+            OutputSetting {
+                on: None,
+                mode: Some("1920x1080".into()),
+                ..make_setting("DP-1")
+            },
+        ];
+
+        let planned = plan_configuration(&outputs, &settings, &HashMap::new()).unwrap();
+
+        assert!(planned[0].enabled);
+        assert!(planned[0].adaptive_sync);
+        assert_eq!(planned[0].scale, 2.0);
+        assert!(!planned[1].enabled);
+    }
+
+    #[test]
+    fn test_plan_custom_and_unknown_mode() {
+        let outputs = vec![make_output("DP-1", true)];
+
+        let settings = vec![OutputSetting {
+            mode: Some("2560x1440@75Hz".into()),
+            ..make_setting("DP-1")
+        }];
+        let planned = plan_configuration(&outputs, &settings, &HashMap::new()).unwrap();
+        assert_eq!(planned[0].current_mode().unwrap().width, 2560);
+
+        let settings = vec![OutputSetting {
+            mode: Some("2560x1440".into()),
+            ..make_setting("DP-1")
+        }];
+        assert!(plan_configuration(&outputs, &settings, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_plan_mode_candidates() {
+        let mut outputs = vec![make_output("DP-1", true)];
+        outputs[0].modes.push(Mode {
+            width: 2560,
+            height: 1440,
+            refresh: 59.951,
+            preferred: false,
+            current: false,
+        });
+
+        let settings = vec![OutputSetting {
+            mode: Some(ModeList(vec![
+                "3840x2160@60".into(),
+                "2560x1440@60".into(),
+                "preferred".into(),
+            ])),
+            ..make_setting("DP-1")
+        }];
+        let planned = plan_configuration(&outputs, &settings, &HashMap::new()).unwrap();
+        assert_eq!(planned[0].current_mode().unwrap().refresh, 59.951);
+
+        // the advertised mode rather than a custom one
+        let settings = vec![OutputSetting {
+            mode: Some("2560x1440@59.95".into()),
+            ..make_setting("DP-1")
+        }];
+        let planned = plan_configuration(&outputs, &settings, &HashMap::new()).unwrap();
+        assert_eq!(planned[0].current_mode().unwrap().refresh, 59.951);
+
+        let settings = vec![OutputSetting {
+            mode: Some(ModeList(vec!["3840x2160".into(), "max-resolution".into()])),
+            ..make_setting("DP-1")
+        }];
+        let planned = plan_configuration(&outputs, &settings, &HashMap::new()).unwrap();
+        assert_eq!(planned[0].current_mode().unwrap().width, 2560);
+    }
+
+    #[test]
+    fn test_resolve_settings() {
+        let mut outputs = vec![
+            make_output("eDP-1", true),
+            make_output("DP-1", true),
+            make_output("HDMI-A-1", true),
+        ];
+        outputs[0].physical_size = PhysicalSize {
+            width: 344,
+            height: 194,
+        };
+        let profile = Profile {
+            settings: vec![
+                OutputSetting {
+                    mode: Some("max-refresh".into()),
+                    scale: Some(Scale::Auto(AutoScale::default())),
+                    ..make_setting("eDP-1")
+                },
+                OutputSetting {
+                    on: Some(false),
+                    mode: Some("preferred".into()),
+                    ..make_setting("DP-1")
+                },
+                // doesn't report its physical size
+                OutputSetting {
+                    scale: Some(Scale::Auto(AutoScale::default())),
+                    ..make_setting("HDMI-A-1")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let resolved = resolve_settings(&profile, &outputs, &HashMap::new()).unwrap();
+        assert_eq!(
+            resolved.settings[0].mode,
+            Some("1920x1080@143.981Hz".into())
+        );
+        assert_eq!(resolved.settings[0].scale, Some(Scale::Fixed(1.0)));
+        assert_eq!(resolved.settings[1].mode, None);
+        assert_eq!(resolved.settings[2].scale, None);
+    }
+
+    #[test]
+    fn test_verify_and_restore() {
+        let snapshot = vec![make_output("eDP-1", true), make_output("DP-1", false)];
+        let settings = vec![
+            OutputSetting {
+                scale: Some(Scale::Fixed(1.1)),
+                pos: Some("0,0".into()),
+                ..make_setting("eDP-1")
+            },
+            make_setting("DP-1"),
+        ];
+        let expected = plan_configuration(&snapshot, &settings, &HashMap::new()).unwrap();
+
+        // the compositor stores the scale as a fixed point number
+        let mut actual = expected.clone();
+        actual[0].scale = 1.1015625;
+        assert!(verify(&expected, &actual).is_ok());
+
+        actual[0].transform = Transform::Rotate90;
+        actual[1].enabled = false;
+        let error = verify(&expected, &actual).unwrap_err().to_string();
+        assert!(error.contains("eDP-1 (transform)"), "{error}");
+        assert!(error.contains("DP-1 (enabled)"), "{error}");
+
+        let restore = restore_profile(&snapshot, &actual);
+        assert_eq!(restore.settings.len(), 1);
+        assert_eq!(restore.settings[0].transform.as_deref(), Some("normal"));
+        assert_eq!(restore.settings[0].scale, Some(Scale::Fixed(1.0)));
+        assert_eq!(restore.settings[0].mode, None);
+
+        actual[0].enabled = false;
+        let restore = restore_profile(&snapshot, &actual);
+        assert_eq!(restore.settings[0].on, Some(true));
+        assert_eq!(restore.settings[0].mode, Some("1920x1080@60Hz".into()));
+    }
+
+    #[test]
+    fn test_plan_unknown_relative_target() {
+        let outputs = vec![make_output("DP-1", true)];
+        let settings = vec![OutputSetting {
+            left_of: Some("HDMI-A-1".into()),
+            ..make_setting("DP-1")
+        }];
+
+        assert!(plan_configuration(&outputs, &settings, &HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,451 @@
+use crate::backend::{OutputBackend, plan_configuration};
+use crate::config::Profile;
+use crate::output::{Mode, OutputInfo, PhysicalSize, Position, Transform, to_millihertz};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use wayland_client::{
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum,
+    backend::ObjectId,
+    event_created_child,
+    globals::{GlobalListContents, registry_queue_init},
+    protocol::{wl_output, wl_registry},
+};
+use wayland_protocols_wlr::output_management::v1::client::{
+    zwlr_output_configuration_head_v1::ZwlrOutputConfigurationHeadV1,
+    zwlr_output_configuration_v1::{self, ZwlrOutputConfigurationV1},
+    zwlr_output_head_v1::{self, AdaptiveSyncState, ZwlrOutputHeadV1},
+    zwlr_output_manager_v1::{self, ZwlrOutputManagerV1},
+    zwlr_output_mode_v1::{self, ZwlrOutputModeV1},
+};
+
+const ADAPTIVE_SYNC_SINCE: u32 = 4;
+const RELEASE_SINCE: u32 = 3;
+
+struct HeadState {
+    proxy: ZwlrOutputHeadV1,
+    name: String,
+    make: Option<String>,
+    model: Option<String>,
+    serial: Option<String>,
+    physical_size: PhysicalSize,
+    enabled: bool,
+    modes: Vec<ZwlrOutputModeV1>,
+    current_mode: Option<ObjectId>,
+    position: Position,
+    transform: Transform,
+    scale: f64,
+    adaptive_sync: bool,
+}
+
+#[derive(Default)]
+struct ModeState {
+    width: i32,
+    height: i32,
+    refresh: i32,
+    preferred: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigurationResult {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Default)]
+struct ManagerState {
+    heads: Vec<HeadState>,
+    modes: HashMap<ObjectId, ModeState>,
+    serial: Option<u32>,
+    /// a `done` event arrived since the outputs were last queried
+    changed: bool,
+    finished: bool,
+    result: Option<ConfigurationResult>,
+}
+
+impl ManagerState {
+    fn mode_to_info(&self, mode: &ZwlrOutputModeV1, current: bool) -> Option<Mode> {
+        self.modes.get(&mode.id()).map(|m| Mode {
+            width: m.width,
+            height: m.height,
+            refresh: f64::from(m.refresh) / 1000.0,
+            preferred: m.preferred,
+            current,
+        })
+    }
+
+    fn outputs(&self) -> Vec<OutputInfo> {
+        self.heads
+            .iter()
+            .map(|head| OutputInfo {
+                name: head.name.clone(),
+                make: head.make.clone(),
+                model: head.model.clone(),
+                serial: head.serial.clone(),
+                physical_size: head.physical_size,
+                enabled: head.enabled,
+                modes: head
+                    .modes
+                    .iter()
+                    .filter_map(|mode| {
+                        let current = head.current_mode.as_ref() == Some(&mode.id());
+                        self.mode_to_info(mode, current)
+                    })
+                    .collect(),
+                position: head.position,
+                transform: head.transform,
+                scale: head.scale,
+                adaptive_sync: head.adaptive_sync,
+            })
+            .collect()
+    }
+}
+
+/// Talks to the compositor over wlr-output-management on its own event queue.
+pub struct NativeBackend {
+    manager: ZwlrOutputManagerV1,
+    event_queue: EventQueue<ManagerState>,
+    state: ManagerState,
+}
+
+impl NativeBackend {
+    pub fn new(conn: &Connection) -> Result<Self> {
+        let (globals, mut event_queue) = registry_queue_init::<ManagerState>(conn)?;
+        let qh = event_queue.handle();
+
+        let manager: ZwlrOutputManagerV1 = globals
+            .bind(&qh, 1..=4, ())
+            .context("Compositor does not support the wlr-output-management protocol")?;
+
+        let mut state = ManagerState::default();
+        while state.serial.is_none() {
+            event_queue.blocking_dispatch(&mut state)?;
+        }
+
+        Ok(Self {
+            manager,
+            event_queue,
+            state,
+        })
+    }
+
+    /// sends `planned` as the new configuration, only testing it when `test` is set
+    fn submit(&mut self, planned: &[OutputInfo], test: bool) -> Result<()> {
+        let serial = self
+            .state
+            .serial
+            .context("No output configuration received from the compositor yet")?;
+
+        let targets = self
+            .state
+            .heads
+            .iter()
+            .map(|head| {
+                planned
+                    .iter()
+                    .find(|o| o.name == head.name)
+                    .with_context(|| format!("No configuration for output '{}'", head.name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let qh = self.event_queue.handle();
+        let configuration = self.manager.create_configuration(serial, &qh, ());
+
+        for (head, target) in self.state.heads.iter().zip(targets) {
+            if !target.enabled {
+                configuration.disable_head(&head.proxy);
+                continue;
+            }
+
+            let config_head = configuration.enable_head(&head.proxy, &qh, ());
+            if let Some(mode) = target.current_mode() {
+                let advertised = head.modes.iter().find(|proxy| {
+                    self.state.modes.get(&proxy.id()).is_some_and(|m| {
+                        m.width == mode.width
+                            && m.height == mode.height
+                            && m.refresh == to_millihertz(mode.refresh)
+                    })
+                });
+                match advertised {
+                    Some(proxy) => config_head.set_mode(proxy),
+                    None => config_head.set_custom_mode(
+                        mode.width,
+                        mode.height,
+                        to_millihertz(mode.refresh),
+                    ),
+                }
+            }
+            config_head.set_position(target.position.x, target.position.y);
+            config_head.set_transform(to_wl_transform(target.transform));
+            config_head.set_scale(target.scale);
+            if config_head.version() >= ADAPTIVE_SYNC_SINCE {
+                config_head.set_adaptive_sync(if target.adaptive_sync {
+                    AdaptiveSyncState::Enabled
+                } else {
+                    AdaptiveSyncState::Disabled
+                });
+            }
+        }
+
+        if test {
+            configuration.test();
+        } else {
+            configuration.apply();
+        }
+        self.state.result = None;
+        while self.state.result.is_none() {
+            self.event_queue.blocking_dispatch(&mut self.state)?;
+        }
+        configuration.destroy();
+
+        match self.state.result.take() {
+            Some(ConfigurationResult::Succeeded) => Ok(()),
+            Some(ConfigurationResult::Cancelled) => {
+                anyhow::bail!("Compositor cancelled the configuration, outputs changed meanwhile")
+            }
+            _ => anyhow::bail!("Compositor rejected the configuration"),
+        }
+    }
+}
+
+impl OutputBackend for NativeBackend {
+    fn outputs(&mut self) -> Result<Vec<OutputInfo>> {
+        self.event_queue.roundtrip(&mut self.state)?;
+        if self.state.finished {
+            anyhow::bail!("Output manager was finished by the compositor");
+        }
+        self.state.changed = false;
+        Ok(self.state.outputs())
+    }
+
+    fn apply(&mut self, profile: &Profile, name_map: &HashMap<String, String>) -> Result<()> {
+        let planned = plan_configuration(&self.outputs()?, &profile.settings, name_map)?;
+        self.submit(&planned, false)
+    }
+
+    fn test(&mut self, profile: &Profile, name_map: &HashMap<String, String>) -> Result<()> {
+        let planned = plan_configuration(&self.outputs()?, &profile.settings, name_map)?;
+        self.submit(&planned, true)
+    }
+
+    fn has_changes(&mut self) -> Result<bool> {
+        // events read from the shared connection by another queue wait here to be dispatched
+        self.event_queue.dispatch_pending(&mut self.state)?;
+        Ok(std::mem::take(&mut self.state.changed))
+    }
+}
+
+fn to_wl_transform(transform: Transform) -> wl_output::Transform {
+    match transform {
+        Transform::Normal => wl_output::Transform::Normal,
+        Transform::Rotate90 => wl_output::Transform::_90,
+        Transform::Rotate180 => wl_output::Transform::_180,
+        Transform::Rotate270 => wl_output::Transform::_270,
+        Transform::Flipped => wl_output::Transform::Flipped,
+        Transform::Flipped90 => wl_output::Transform::Flipped90,
+        Transform::Flipped180 => wl_output::Transform::Flipped180,
+        Transform::Flipped270 => wl_output::Transform::Flipped270,
+    }
+}
+
+fn from_wl_transform(transform: wl_output::Transform) -> Transform {
+    match transform {
+        wl_output::Transform::_90 => Transform::Rotate90,
+        wl_output::Transform::_180 => Transform::Rotate180,
+        wl_output::Transform::_270 => Transform::Rotate270,
+        wl_output::Transform::Flipped => Transform::Flipped,
+        wl_output::Transform::Flipped90 => Transform::Flipped90,
+        wl_output::Transform::Flipped180 => Transform::Flipped180,
+        wl_output::Transform::Flipped270 => Transform::Flipped270,
+        _ => Transform::Normal,
+    }
+}
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for ManagerState {
+    fn event(
+        _state: &mut Self,
+        _registry: &wl_registry::WlRegistry,
+        _event: wl_registry::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrOutputManagerV1, ()> for ManagerState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwlrOutputManagerV1,
+        event: zwlr_output_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_output_manager_v1::Event::Head { head } => {
+                state.heads.push(HeadState {
+                    proxy: head,
+                    name: String::new(),
+                    make: None,
+                    model: None,
+                    serial: None,
+                    physical_size: PhysicalSize::default(),
+                    enabled: false,
+                    modes: Vec::new(),
+                    current_mode: None,
+                    position: Position::default(),
+                    transform: Transform::Normal,
+                    scale: 1.0,
+                    adaptive_sync: false,
+                });
+            }
+            zwlr_output_manager_v1::Event::Done { serial } => {
+                log::debug!("Output configuration done, serial {serial}");
+                state.serial = Some(serial);
+                state.changed = true;
+            }
+            zwlr_output_manager_v1::Event::Finished => {
+                log::warn!("Output manager finished by the compositor");
+                state.finished = true;
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(ManagerState, ZwlrOutputManagerV1, [
+        zwlr_output_manager_v1::EVT_HEAD_OPCODE => (ZwlrOutputHeadV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrOutputHeadV1, ()> for ManagerState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrOutputHeadV1,
+        event: zwlr_output_head_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwlr_output_head_v1::Event::Finished = event {
+            if let Some(head) = state.heads.iter().find(|h| h.proxy == *proxy) {
+                for mode in &head.modes {
+                    state.modes.remove(&mode.id());
+                }
+            }
+            state.heads.retain(|h| h.proxy != *proxy);
+            if proxy.version() >= RELEASE_SINCE {
+                proxy.release();
+            }
+            return;
+        }
+
+        let Some(head) = state.heads.iter_mut().find(|h| h.proxy == *proxy) else {
+            return;
+        };
+
+        match event {
+            zwlr_output_head_v1::Event::Name { name } => head.name = name,
+            zwlr_output_head_v1::Event::Make { make } => head.make = Some(make),
+            zwlr_output_head_v1::Event::Model { model } => head.model = Some(model),
+            zwlr_output_head_v1::Event::SerialNumber { serial_number } => {
+                head.serial = Some(serial_number);
+            }
+            zwlr_output_head_v1::Event::PhysicalSize { width, height } => {
+                head.physical_size = PhysicalSize { width, height };
+            }
+            zwlr_output_head_v1::Event::Enabled { enabled } => {
+                head.enabled = enabled != 0;
+                if !head.enabled {
+                    head.current_mode = None;
+                }
+            }
+            zwlr_output_head_v1::Event::Mode { mode } => {
+                state.modes.insert(mode.id(), ModeState::default());
+                head.modes.push(mode);
+            }
+            zwlr_output_head_v1::Event::CurrentMode { mode } => {
+                head.current_mode = Some(mode.id());
+            }
+            zwlr_output_head_v1::Event::Position { x, y } => head.position = Position { x, y },
+            zwlr_output_head_v1::Event::Transform {
+                transform: WEnum::Value(transform),
+            } => head.transform = from_wl_transform(transform),
+            zwlr_output_head_v1::Event::Scale { scale } => head.scale = scale,
+            zwlr_output_head_v1::Event::AdaptiveSync {
+                state: WEnum::Value(adaptive_sync),
+            } => head.adaptive_sync = adaptive_sync == AdaptiveSyncState::Enabled,
+            _ => {}
+        }
+    }
+
+    event_created_child!(ManagerState, ZwlrOutputHeadV1, [
+        zwlr_output_head_v1::EVT_MODE_OPCODE => (ZwlrOutputModeV1, ()),
+    ]);
+}
+
+impl Dispatch<ZwlrOutputModeV1, ()> for ManagerState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrOutputModeV1,
+        event: zwlr_output_mode_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwlr_output_mode_v1::Event::Finished = event {
+            state.modes.remove(&proxy.id());
+            for head in &mut state.heads {
+                head.modes.retain(|m| m != proxy);
+            }
+            if proxy.version() >= RELEASE_SINCE {
+                proxy.release();
+            }
+            return;
+        }
+
+        let Some(mode) = state.modes.get_mut(&proxy.id()) else {
+            return;
+        };
+
+        match event {
+            zwlr_output_mode_v1::Event::Size { width, height } => {
+                mode.width = width;
+                mode.height = height;
+            }
+            zwlr_output_mode_v1::Event::Refresh { refresh } => mode.refresh = refresh,
+            zwlr_output_mode_v1::Event::Preferred => mode.preferred = true,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationV1, ()> for ManagerState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwlrOutputConfigurationV1,
+        event: zwlr_output_configuration_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        state.result = match event {
+            zwlr_output_configuration_v1::Event::Succeeded => Some(ConfigurationResult::Succeeded),
+            zwlr_output_configuration_v1::Event::Failed => Some(ConfigurationResult::Failed),
+            zwlr_output_configuration_v1::Event::Cancelled => Some(ConfigurationResult::Cancelled),
+            _ => return,
+        };
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationHeadV1, ()> for ManagerState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &ZwlrOutputConfigurationHeadV1,
+        _event: <ZwlrOutputConfigurationHeadV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
@@ -0,0 +1,63 @@
+use crate::backend::OutputBackend;
+use crate::config::Profile;
+use crate::output::OutputInfo;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::process::Command;
+
+/// Shells out to the wlr-randr binary for both querying and applying.
+#[derive(Debug, Default)]
+pub struct WlrRandrBackend;
+
+impl OutputBackend for WlrRandrBackend {
+    fn outputs(&mut self) -> Result<Vec<OutputInfo>> {
+        let output = Command::new("wlr-randr")
+            .arg("--json")
+            .output()
+            .context("Failed to run wlr-randr")?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("wlr-randr failed: {stderr}");
+        }
+
+        let outputs: Vec<OutputInfo> =
+            serde_json::from_slice(&output.stdout).context("Failed to parse wlr-randr output")?;
+
+        Ok(outputs)
+    }
+
+    fn apply(&mut self, profile: &Profile, name_map: &HashMap<String, String>) -> Result<()> {
+        match profile.generate_wlr_randr_args(name_map) {
+            Some(args) => run_wlr_randr(&args),
+            None => Ok(()),
+        }
+    }
+
+    fn test(&mut self, profile: &Profile, name_map: &HashMap<String, String>) -> Result<()> {
+        match profile.generate_wlr_randr_args(name_map) {
+            Some(mut args) => {
+                args.push("--dryrun".to_string());
+                run_wlr_randr(&args)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// runs wlr-randr with `args` as they are, without a shell in between
+fn run_wlr_randr(args: &[String]) -> Result<()> {
+    let mut command = Command::new("wlr-randr");
+    command.args(args);
+    log::debug!("Executing command: {command:?}");
+    let output = command
+        .output()
+        .with_context(|| format!("Failed to execute command {command:?}"))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("wlr-randr failed ({}): {}", output.status, stderr.trim());
+    }
+
+    Ok(())
+}
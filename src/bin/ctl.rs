@@ -1,7 +1,11 @@
-use auto_wlr_randr::ipc::{Command, get_socket_path};
+use auto_wlr_randr::check;
+use auto_wlr_randr::config::default_config_path;
+use auto_wlr_randr::ipc::{Command, SwitchOptions, get_socket_path};
 use clap::{Parser, Subcommand};
 use std::io::{Read, Write};
 use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(
@@ -36,11 +40,70 @@ enum CliCommand {
     Switch {
         /// Name of the profile to switch to
         profile_name: String,
+
+        /// Switch back unless `confirm` is run within this many seconds
+        #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+        confirm: Option<u64>,
+
+        /// Keep the profile even when the connected outputs change
+        #[arg(long)]
+        sticky: bool,
+    },
+
+    /// Keep the profile of a switch made with --confirm
+    ///
+    /// Without it, the daemon switches back to the previous profile once the
+    /// timeout given to `switch --confirm` runs out.
+    Confirm,
+
+    /// Switch to the next profile matching the connected outputs
+    ///
+    /// Cycles through every profile matching the connected outputs, from the one
+    /// chosen automatically down in priority, wrapping around. The profile is
+    /// pinned like with `switch`.
+    Next,
+
+    /// Switch to the previous profile matching the connected outputs
+    ///
+    /// Same as `next`, in the opposite direction.
+    Prev,
+
+    /// Stop switching profiles automatically
+    ///
+    /// Profiles then only change when switched to by hand, until `unlock`.
+    Lock,
+
+    /// Switch profiles automatically again
+    ///
+    /// Applies the profile matching the connected outputs right away.
+    Unlock,
+
+    /// Check whether the compositor would accept a profile
+    ///
+    /// Asks the compositor to test the output settings of the specified profile
+    /// without applying them.
+    Test {
+        /// Name of the profile to test
+        profile_name: String,
+    },
+
+    /// Validate a configuration file
+    ///
+    /// Checks the configuration file for mistakes like invalid modes, positions or
+    /// output patterns without talking to the daemon.
+    Check {
+        /// Configuration file to check, defaults to ~/.config/auto-wlr-randr/config.toml
+        file: Option<PathBuf>,
     },
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    if let CliCommand::Check { file } = &cli.command {
+        let path = file.clone().unwrap_or_else(default_config_path);
+        std::process::exit(if check::report(path) { 0 } else { 1 });
+    }
+
     let socket_path = get_socket_path();
 
     if !socket_path.exists() {
@@ -56,7 +119,24 @@ fn main() -> anyhow::Result<()> {
     let command = match cli.command {
         CliCommand::Reload => Command::Reload,
         CliCommand::Status => Command::Status,
-        CliCommand::Switch { profile_name } => Command::Switch(profile_name),
+        CliCommand::Switch {
+            profile_name,
+            confirm,
+            sticky,
+        } => Command::Switch(
+            profile_name,
+            SwitchOptions {
+                confirm: confirm.map(Duration::from_secs),
+                sticky,
+            },
+        ),
+        CliCommand::Confirm => Command::Confirm,
+        CliCommand::Next => Command::Next,
+        CliCommand::Prev => Command::Prev,
+        CliCommand::Lock => Command::Lock,
+        CliCommand::Unlock => Command::Unlock,
+        CliCommand::Test { profile_name } => Command::Test(profile_name),
+        CliCommand::Check { .. } => unreachable!(),
     };
 
     let request = serde_json::to_vec(&command)?;
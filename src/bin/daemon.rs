@@ -2,7 +2,7 @@ use anyhow::{Context, Result};
 use clap::Parser;
 use std::path::PathBuf;
 
-use auto_wlr_randr::{config::Config, event_loop};
+use auto_wlr_randr::{backend::BackendKind, check, config::Config, event_loop};
 
 #[derive(Parser)]
 #[command(
@@ -16,6 +16,14 @@ struct Cli {
     #[arg(short, long)]
     config: PathBuf,
 
+    /// How to query and configure outputs
+    #[arg(short, long, value_enum, default_value_t = BackendKind::Native)]
+    backend: BackendKind,
+
+    /// Validate the configuration file and exit
+    #[arg(long)]
+    check: bool,
+
     /// Set log verbosity level
     #[arg(short, long, default_value = "info")]
     log_level: String,
@@ -28,12 +36,16 @@ fn main() -> Result<()> {
         .init();
 
     let config_path = &cli.config;
+    if cli.check {
+        std::process::exit(if check::report(config_path) { 0 } else { 1 });
+    }
+
     log::info!("Loading configuration from: {:?}", config_path);
     let config = Config::load_from_file(config_path)
         .with_context(|| format!("Failed to load configuration from {:?}", config_path))?;
 
     log::info!("Configuration loaded successfully.");
 
-    event_loop::start_event_loop(config)?;
+    event_loop::start_event_loop(config, cli.backend)?;
     Ok(())
 }
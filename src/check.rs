@@ -0,0 +1,310 @@
+use crate::config::{Config, find_named};
+use crate::output::{ModeCandidate, Position, Transform, is_connector_name};
+use anyhow::{Context, Result};
+use glob::Pattern;
+use indexmap::IndexMap;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use toml::Spanned;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// might be fine depending on the outputs connected
+    Warning,
+}
+
+/// a problem found in the config file that parsing alone doesn't catch
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub profile: String,
+    /// 1-based line and column of the offending value
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: profile '{}': {}{}",
+            self.line,
+            self.column,
+            self.profile,
+            match self.severity {
+                Severity::Error => "",
+                Severity::Warning => "warning: ",
+            },
+            self.message
+        )
+    }
+}
+
+// Shadow of the config keeping the location of every value that gets validated,
+// unknown fields are already rejected by `Config` itself.
+
+#[derive(Deserialize)]
+struct SpannedConfig {
+    #[serde(default, rename = "profile")]
+    profiles: IndexMap<String, SpannedProfile>,
+}
+
+#[derive(Deserialize)]
+struct SpannedProfile {
+    #[serde(default)]
+    layout: Vec<Spanned<String>>,
+    #[serde(default)]
+    settings: Vec<SpannedSetting>,
+}
+
+#[derive(Deserialize)]
+struct SpannedSetting {
+    output: Spanned<String>,
+    #[serde(default)]
+    alias: Option<String>,
+    #[serde(default)]
+    mode: Option<Spanned<toml::Value>>,
+    #[serde(default)]
+    pos: Option<Spanned<String>>,
+    #[serde(default)]
+    left_of: Option<Spanned<String>>,
+    #[serde(default)]
+    right_of: Option<Spanned<String>>,
+    #[serde(default)]
+    above: Option<Spanned<String>>,
+    #[serde(default)]
+    below: Option<Spanned<String>>,
+    #[serde(default)]
+    transform: Option<Spanned<String>>,
+    #[serde(default)]
+    scale: Option<Spanned<toml::Value>>,
+}
+
+impl SpannedSetting {
+    fn relative(&self) -> [(&'static str, &Option<Spanned<String>>); 4] {
+        [
+            ("left_of", &self.left_of),
+            ("right_of", &self.right_of),
+            ("above", &self.above),
+            ("below", &self.below),
+        ]
+    }
+}
+
+struct Checker<'a> {
+    content: &'a str,
+    profile: &'a str,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, span: Range<usize>, message: impl Into<String>) {
+        self.push(Severity::Error, span, message.into());
+    }
+
+    fn warn(&mut self, span: Range<usize>, message: impl Into<String>) {
+        self.push(Severity::Warning, span, message.into());
+    }
+
+    fn push(&mut self, severity: Severity, span: Range<usize>, message: String) {
+        let before = &self.content[..span.start.min(self.content.len())];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        self.diagnostics.push(Diagnostic {
+            severity,
+            profile: self.profile.to_string(),
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message,
+        });
+    }
+
+    /// Reports `target` unless it refers to an output of the profile, other than the
+    /// one of `settings[except]`.
+    fn check_target(
+        &mut self,
+        target: &Spanned<String>,
+        settings: &[SpannedSetting],
+        except: Option<usize>,
+        describe: &str,
+    ) {
+        match resolve_target(settings, target.get_ref(), except) {
+            Target::Named => {}
+            Target::Connector => self.warn(
+                target.span(),
+                format!(
+                    "{describe} '{}' is not an alias or output of this profile, it only works if \
+                     one of the outputs is connected as '{}'",
+                    target.get_ref(),
+                    target.get_ref()
+                ),
+            ),
+            Target::Unknown => self.report(
+                target.span(),
+                format!(
+                    "{describe} '{}' is not an output of this profile",
+                    target.get_ref()
+                ),
+            ),
+        }
+    }
+
+    fn check_setting(&mut self, idx: usize, settings: &[SpannedSetting]) {
+        let setting = &settings[idx];
+        let output = &setting.output;
+        if let Err(e) = Pattern::new(output.get_ref()) {
+            self.report(
+                output.span(),
+                format!("Invalid output pattern '{}': {e}", output.get_ref()),
+            );
+        }
+
+        if let Some(mode) = &setting.mode {
+            let candidates = match mode.get_ref() {
+                toml::Value::Array(items) => items.iter().filter_map(|i| i.as_str()).collect(),
+                value => value.as_str().into_iter().collect::<Vec<_>>(),
+            };
+            for candidate in candidates {
+                if let Err(e) = candidate.parse::<ModeCandidate>() {
+                    self.report(mode.span(), e.to_string());
+                }
+            }
+        }
+
+        if let Some(transform) = &setting.transform
+            && let Err(e) = transform.get_ref().parse::<Transform>()
+        {
+            self.report(transform.span(), e.to_string());
+        }
+
+        if let Some(scale) = &setting.scale {
+            let invalid = match scale.get_ref() {
+                toml::Value::Float(value) => *value <= 0.0 || value.is_nan(),
+                toml::Value::Integer(value) => *value <= 0,
+                toml::Value::String(keyword) => keyword != "auto",
+                // tables are validated when the config is loaded
+                _ => false,
+            };
+            if invalid {
+                self.report(
+                    scale.span(),
+                    format!(
+                        "Invalid scale '{}', expected a positive number or \"auto\"",
+                        scale.get_ref()
+                    ),
+                );
+            }
+        }
+
+        if let Some(pos) = &setting.pos
+            && let Err(e) = pos.get_ref().parse::<Position>()
+        {
+            self.report(pos.span(), e.to_string());
+        }
+
+        for (key, target) in setting.relative() {
+            let Some(target) = target else { continue };
+
+            if setting.pos.is_some() {
+                self.report(
+                    target.span(),
+                    format!("'{key}' can't be combined with 'pos' in the same setting"),
+                );
+            }
+
+            self.check_target(target, settings, Some(idx), &format!("'{key}' target"));
+        }
+    }
+}
+
+enum Target {
+    /// the alias or output of a setting
+    Named,
+    /// a connector one of the output patterns may match, only known once connected
+    Connector,
+    Unknown,
+}
+
+/// Resolves `target` the way `Profile::find_setting` does, as far as that's possible
+/// without knowing the connected outputs.
+fn resolve_target(settings: &[SpannedSetting], target: &str, except: Option<usize>) -> Target {
+    let named = find_named(
+        settings
+            .iter()
+            .map(|s| (s.alias.as_deref(), s.output.get_ref().as_str())),
+        target,
+    );
+    if named.is_some_and(|idx| Some(idx) != except) {
+        return Target::Named;
+    }
+
+    // outputs given as a connector always match just that one
+    let may_match = settings
+        .iter()
+        .enumerate()
+        .any(|(idx, s)| Some(idx) != except && !is_connector_name(s.output.get_ref()));
+    if may_match && is_connector_name(target) {
+        Target::Connector
+    } else {
+        Target::Unknown
+    }
+}
+
+/// Validates everything about `content` that deserializing into `Config` doesn't.
+pub fn check_str(content: &str) -> Result<Vec<Diagnostic>> {
+    let config: SpannedConfig = toml::from_str(content).context("Failed to parse config")?;
+
+    let mut diagnostics = Vec::new();
+    for (profile_id, profile) in &config.profiles {
+        let mut checker = Checker {
+            content,
+            profile: profile_id,
+            diagnostics: Vec::new(),
+        };
+        for idx in 0..profile.settings.len() {
+            checker.check_setting(idx, &profile.settings);
+        }
+        for entry in &profile.layout {
+            checker.check_target(entry, &profile.settings, None, "Layout entry");
+        }
+        diagnostics.append(&mut checker.diagnostics);
+    }
+
+    Ok(diagnostics)
+}
+
+/// Loads the config at `path` and validates it, parse errors are returned as `Err`.
+pub fn check_file<P: AsRef<Path>>(path: P) -> Result<Vec<Diagnostic>> {
+    let path = path.as_ref();
+    Config::load_from_file(path)?;
+
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file at {path:?}"))?;
+    check_str(&content)
+}
+
+/// Prints the result of checking `path`, returns whether the config is valid, which
+/// it still is with warnings.
+pub fn report<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref();
+    match check_file(path) {
+        Ok(diagnostics) => {
+            for diagnostic in &diagnostics {
+                eprintln!("{}:{diagnostic}", path.display());
+            }
+            let valid = diagnostics.iter().all(|d| d.severity == Severity::Warning);
+            if valid {
+                println!("{}: OK", path.display());
+            }
+            valid
+        }
+        Err(e) => {
+            eprintln!("{e:#}");
+            false
+        }
+    }
+}
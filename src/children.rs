@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use std::io::{self, ErrorKind, Read};
+use std::mem::{MaybeUninit, size_of};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::process::{Child, ChildStderr, Command, ExitStatus, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// how often a waited for command is checked on
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+struct TrackedChild {
+    description: String,
+    child: Child,
+}
+
+/// Commands spawned by the daemon, kept around until they exit so they don't linger
+/// as zombies and their result ends up in the log.
+#[derive(Default)]
+pub struct ChildTracker {
+    children: Vec<TrackedChild>,
+}
+
+impl ChildTracker {
+    /// Spawns `command`, `description` is used when logging. Its stderr goes wherever the
+    /// daemon's does, a pipe nobody reads would block long-running commands once full.
+    pub fn spawn(&mut self, mut command: Command, description: &str) -> io::Result<()> {
+        let child = command.spawn()?;
+        log::debug!("Spawned '{description}' as pid {}", child.id());
+        self.children.push(TrackedChild {
+            description: description.to_string(),
+            child,
+        });
+        Ok(())
+    }
+
+    /// number of children that haven't been reaped yet
+    pub fn running(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Collects every child that has exited and logs how it ended.
+    pub fn reap(&mut self) {
+        self.children
+            .retain_mut(|tracked| match tracked.child.try_wait() {
+                Ok(Some(status)) => {
+                    if status.success() {
+                        log::debug!("Command '{}' finished", tracked.description);
+                    } else {
+                        log::warn!("Command '{}' failed ({status})", tracked.description);
+                    }
+                    false
+                }
+                Ok(None) => true,
+                Err(e) => {
+                    log::error!("Failed to check on '{}': {e}", tracked.description);
+                    false
+                }
+            });
+    }
+}
+
+/// Reads whatever `pipe` has available without blocking, until it would block or closes.
+fn drain_pipe(pipe: &mut ChildStderr, buf: &mut Vec<u8>) {
+    let mut chunk = [0; 4096];
+    loop {
+        match pipe.read(&mut chunk) {
+            Ok(0) => return,
+            Ok(read) => buf.extend_from_slice(&chunk[..read]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            // WouldBlock, or the pipe is unusable and there is nothing more to get
+            Err(_) => return,
+        }
+    }
+}
+
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Waits for `child` to exit, collecting its stderr meanwhile so a chatty command can't
+/// fill the pipe and stall. Doesn't wait for the pipe to close, processes the command
+/// started in the background may keep it open.
+fn wait_timeout(
+    child: &mut Child,
+    timeout: Duration,
+    stderr: &mut Vec<u8>,
+) -> io::Result<Option<ExitStatus>> {
+    let mut pipe = child.stderr.take();
+    if let Some(pipe) = &pipe {
+        set_nonblocking(pipe.as_raw_fd())?;
+    }
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(pipe) = pipe.as_mut() {
+            drain_pipe(pipe, stderr);
+        }
+        if let Some(status) = child.try_wait()? {
+            if let Some(pipe) = pipe.as_mut() {
+                drain_pipe(pipe, stderr);
+            }
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        sleep(WAIT_INTERVAL);
+    }
+}
+
+/// Runs `command` to completion, killing it once `timeout` passes.
+pub fn run_and_wait(mut command: Command, description: &str, timeout: Duration) -> Result<()> {
+    log::debug!("Running '{description}'");
+    let mut child = command
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute command '{description}'"))?;
+
+    let mut stderr = Vec::new();
+    let Some(status) = wait_timeout(&mut child, timeout, &mut stderr)? else {
+        let _ = child.kill();
+        let _ = child.wait();
+        anyhow::bail!("Command '{description}' timed out after {timeout:?} and was killed");
+    };
+
+    let stderr = String::from_utf8_lossy(&stderr);
+    let stderr = stderr.trim();
+    if !status.success() {
+        if stderr.is_empty() {
+            anyhow::bail!("Command '{description}' failed ({status})");
+        }
+        anyhow::bail!("Command '{description}' failed ({status}): {stderr}");
+    }
+    if !stderr.is_empty() {
+        log::debug!("stderr of '{description}': {stderr}");
+    }
+    Ok(())
+}
+
+/// signalfd becoming readable whenever a child exits
+pub struct SigchldFd {
+    fd: OwnedFd,
+}
+
+impl SigchldFd {
+    /// Blocks SIGCHLD for the calling thread so it is only delivered through the fd,
+    /// has to be called before any other thread is started.
+    pub fn new() -> Result<Self> {
+        unsafe {
+            let mut mask = MaybeUninit::<libc::sigset_t>::uninit();
+            libc::sigemptyset(mask.as_mut_ptr());
+            let mut mask = mask.assume_init();
+            libc::sigaddset(&mut mask, libc::SIGCHLD);
+
+            let ret = libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut());
+            if ret != 0 {
+                return Err(io::Error::from_raw_os_error(ret)).context("Failed to block SIGCHLD");
+            }
+
+            let fd = libc::signalfd(-1, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC);
+            if fd < 0 {
+                return Err(io::Error::last_os_error()).context("Failed to create signalfd");
+            }
+            Ok(Self {
+                fd: OwnedFd::from_raw_fd(fd),
+            })
+        }
+    }
+
+    /// Consumes pending notifications, several exits may be coalesced into one.
+    pub fn drain(&self) -> Result<()> {
+        let mut info = MaybeUninit::<libc::signalfd_siginfo>::uninit();
+        loop {
+            let read = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    info.as_mut_ptr().cast(),
+                    size_of::<libc::signalfd_siginfo>(),
+                )
+            };
+            if read < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == ErrorKind::WouldBlock {
+                    return Ok(());
+                }
+                return Err(e).context("Failed to read from signalfd");
+            }
+        }
+    }
+}
+
+impl AsRawFd for SigchldFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
@@ -1,22 +1,31 @@
-use crate::output::OutputInfo;
+use crate::backend::plan_configuration;
+use crate::output::{MatchKind, OutputInfo, Position};
 use anyhow::{Context, Result};
 use glob::Pattern;
+use indexmap::IndexMap;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// mirrors wlr-randr's output settings
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, Default)]
 #[serde(deny_unknown_fields)]
 pub struct OutputSetting {
     pub output: String,
 
+    /// short name `layout` and relative position targets can refer to the setting by
+    #[serde(default)]
+    pub alias: Option<String>,
+
+    /// `None` leaves the output enabled or disabled as it is
     #[serde(default)]
-    pub on: bool,
+    pub on: Option<bool>,
 
     #[serde(default)]
-    pub mode: Option<String>,
+    pub mode: Option<ModeList>,
 
     #[serde(default)]
     pub preferred: bool,
@@ -37,18 +46,305 @@ pub struct OutputSetting {
     pub transform: Option<String>,
 
     #[serde(default)]
-    pub scale: Option<f32>,
+    pub scale: Option<Scale>,
 
+    /// `None` leaves adaptive sync as it is
     #[serde(default)]
-    pub adaptive_sync: bool,
+    pub adaptive_sync: Option<bool>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// what to do with connected outputs that no setting of a profile matched
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExtraOutputs {
+    /// the profile only matches when every connected output is matched
+    #[default]
+    Reject,
+    /// leave them as they are
+    Keep,
+    /// turn them off
+    Off,
+    /// turn them on at their preferred mode, placed left-to-right after the profile's outputs
+    Auto,
+}
+
+/// axis the outputs of a `layout` are placed along
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LayoutDirection {
+    /// left to right
+    #[default]
+    Horizontal,
+    /// top to bottom
+    Vertical,
+}
+
+/// where outputs of different sizes sit across the `layout` direction
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LayoutAlign {
+    #[default]
+    #[serde(alias = "left")]
+    Top,
+    Center,
+    #[serde(alias = "right")]
+    Bottom,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FallbackLayout {
+    /// leave the outputs as the compositor set them up
+    #[default]
+    Keep,
+    /// turn every output on at its preferred mode, placed left-to-right in connector order
+    Auto,
+}
+
+/// reported as the active profile while the fallback is applied
+pub const FALLBACK_PROFILE_ID: &str = "(fallback)";
+
+/// applied when no profile matches the connected outputs
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Fallback {
+    #[serde(default)]
+    pub layout: FallbackLayout,
+
+    #[serde(default)]
+    pub pre_exec: Vec<Hook>,
+
+    #[serde(default)]
+    pub post_exec: Vec<Hook>,
+
+    #[serde(default)]
+    pub exec: Vec<String>,
+
+    #[serde(default)]
+    pub on_deactivate: Vec<Hook>,
+}
+
+impl Fallback {
+    pub fn to_profile(&self) -> Profile {
+        Profile {
+            extra_outputs: match self.layout {
+                FallbackLayout::Keep => ExtraOutputs::Keep,
+                FallbackLayout::Auto => ExtraOutputs::Auto,
+            },
+            pre_exec: self.pre_exec.clone(),
+            post_exec: self.post_exec.clone(),
+            exec: self.exec.clone(),
+            on_deactivate: self.on_deactivate.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// how long `pre_exec` and `post_exec` hooks may run before they get killed
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HookTable {
+    command: String,
+    #[serde(default)]
+    timeout: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ModeListSpec {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// `mode` of a setting, a single entry or candidates tried in order until one is
+/// available, see [`crate::output::ModeCandidate`]
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(from = "ModeListSpec")]
+pub struct ModeList(pub Vec<String>);
+
+impl From<ModeListSpec> for ModeList {
+    fn from(spec: ModeListSpec) -> Self {
+        match spec {
+            ModeListSpec::One(mode) => Self(vec![mode]),
+            ModeListSpec::Many(modes) => Self(modes),
+        }
+    }
+}
+
+impl From<&str> for ModeList {
+    fn from(mode: &str) -> Self {
+        Self(vec![mode.to_string()])
+    }
+}
+
+impl fmt::Display for ModeList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.join(", "))
+    }
+}
+
+/// target DPI of `scale = "auto"` for built-in panels, which are looked at from closer
+pub const DEFAULT_INTERNAL_DPI: f64 = 135.0;
+/// target DPI of `scale = "auto"` for other outputs
+pub const DEFAULT_EXTERNAL_DPI: f64 = 110.0;
+/// what an automatic scale gets rounded to by default
+pub const DEFAULT_SCALE_STEP: f64 = 0.25;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AutoScaleTable {
+    dpi: Option<f64>,
+    step: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScaleSpec {
+    Fixed(f32),
+    Keyword(String),
+    Auto(AutoScaleTable),
+}
+
+/// scale computed from the DPI of the output's mode and physical size
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AutoScale {
+    /// `None` picks a default depending on whether the output is built-in
+    pub dpi: Option<f64>,
+    pub step: Option<f64>,
+}
+
+impl AutoScale {
+    /// Scale that brings `output` closest to the target DPI, rounded to the step and
+    /// never below 1. `None` if the output doesn't report its physical size.
+    pub fn scale_for(&self, output: &OutputInfo) -> Option<f64> {
+        let target = self.dpi.unwrap_or(if output.is_internal() {
+            DEFAULT_INTERNAL_DPI
+        } else {
+            DEFAULT_EXTERNAL_DPI
+        });
+        let step = self.step.unwrap_or(DEFAULT_SCALE_STEP);
+        let scale = (output.dpi()? / target / step).round() * step;
+        Some(scale.max(1.0))
+    }
+}
+
+/// `scale` of a setting, a number, `"auto"` or `{ dpi = DPI, step = STEP }` for an
+/// automatic scale with different rounding or target DPI
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "ScaleSpec")]
+pub enum Scale {
+    Fixed(f32),
+    Auto(AutoScale),
+}
+
+impl TryFrom<ScaleSpec> for Scale {
+    type Error = String;
+
+    fn try_from(spec: ScaleSpec) -> std::result::Result<Self, Self::Error> {
+        match spec {
+            ScaleSpec::Fixed(scale) => Ok(Self::Fixed(scale)),
+            ScaleSpec::Keyword(keyword) if keyword == "auto" => {
+                Ok(Self::Auto(AutoScale::default()))
+            }
+            ScaleSpec::Keyword(keyword) => Err(format!(
+                "invalid scale '{keyword}', expected a number or \"auto\""
+            )),
+            ScaleSpec::Auto(AutoScaleTable { dpi, step }) => {
+                for (key, value) in [("dpi", dpi), ("step", step)] {
+                    if let Some(value) = value
+                        && !(value > 0.0 && value.is_finite())
+                    {
+                        return Err(format!("invalid scale {key} {value}"));
+                    }
+                }
+                Ok(Self::Auto(AutoScale { dpi, step }))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HookSpec {
+    Command(String),
+    Table(HookTable),
+}
+
+/// command run and waited for around applying a profile, either a plain string or
+/// `{ command = "...", timeout = SECONDS }`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "HookSpec")]
+pub struct Hook {
+    pub command: String,
+    pub timeout: Duration,
+}
+
+impl Hook {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            timeout: DEFAULT_HOOK_TIMEOUT,
+        }
+    }
+}
+
+impl TryFrom<HookSpec> for Hook {
+    type Error = String;
+
+    fn try_from(spec: HookSpec) -> std::result::Result<Self, Self::Error> {
+        match spec {
+            HookSpec::Command(command) => Ok(Self::new(command)),
+            HookSpec::Table(HookTable { command, timeout }) => {
+                let timeout = match timeout {
+                    Some(secs) => Duration::try_from_secs_f64(secs)
+                        .map_err(|_| format!("invalid hook timeout {secs}"))?,
+                    None => DEFAULT_HOOK_TIMEOUT,
+                };
+                Ok(Self { command, timeout })
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
 #[serde(deny_unknown_fields)]
 pub struct Profile {
+    /// when several profiles match, the highest priority wins, then the most specific
+    /// match, then the one defined first in the file
+    #[serde(default)]
+    pub priority: i32,
+
+    #[serde(default)]
+    pub extra_outputs: ExtraOutputs,
+
+    /// outputs placed next to each other in this order, by alias, pattern or name
+    #[serde(default)]
+    pub layout: Vec<String>,
+
+    #[serde(default)]
+    pub layout_direction: LayoutDirection,
+
+    #[serde(default)]
+    pub layout_align: LayoutAlign,
+
+    /// run one after another before the output settings are applied
+    #[serde(default)]
+    pub pre_exec: Vec<Hook>,
+
+    /// run one after another once the output settings were applied successfully
+    #[serde(default)]
+    pub post_exec: Vec<Hook>,
+
+    /// started in the background once the output settings were applied successfully
     #[serde(default)]
     pub exec: Vec<String>,
 
+    /// run one after another when the profile stops being active
+    #[serde(default)]
+    pub on_deactivate: Vec<Hook>,
+
     #[serde(default)]
     pub settings: Vec<OutputSetting>,
 }
@@ -57,82 +353,397 @@ pub struct Profile {
 #[serde(deny_unknown_fields)]
 pub struct Config {
     #[serde(rename = "profile")]
-    pub profiles: HashMap<String, Profile>,
+    pub profiles: IndexMap<String, Profile>,
+
+    #[serde(default)]
+    pub fallback: Option<Fallback>,
+
+    /// ask the compositor to test output settings before applying them
+    #[serde(default)]
+    pub test_before_apply: bool,
+
+    /// treat unset `on` and `adaptive_sync` as false like older versions did, instead of
+    /// leaving the output as it is
+    #[serde(default)]
+    pub legacy_output_defaults: bool,
 
     #[serde(skip)]
     config_path: String,
 }
 
-impl Profile {
-    pub fn generate_commands(&self, output_name_map: &HashMap<String, String>) -> Vec<String> {
-        let mut commands = Vec::with_capacity(self.exec.len() + 1);
+/// how precisely a profile's patterns matched, compared by the most specific match first
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Specificity(Vec<MatchKind>);
+
+impl Specificity {
+    fn new(mut kinds: Vec<MatchKind>) -> Self {
+        kinds.sort_unstable_by(|a, b| b.cmp(a));
+        Self(kinds)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileMatch {
+    /// pattern of each setting mapped to the name of the output it matched
+    pub name_map: HashMap<String, String>,
+    pub specificity: Specificity,
+}
 
+/// Backtracking search for an assignment of settings to distinct outputs.
+///
+/// Every valid assignment is considered, so a greedy wildcard can't steal the only output
+/// a more specific pattern could take. The most specific assignment is kept, ties go to
+/// the one found first, which prefers outputs in their connection order.
+struct AssignmentSearch<'a> {
+    /// for each setting, the outputs its pattern matched and how
+    candidates: &'a [Vec<(usize, MatchKind)>],
+    used: Vec<bool>,
+    assignment: Vec<(usize, MatchKind)>,
+    best: Option<(Vec<usize>, Specificity)>,
+    /// every setting matched as specifically as it can, no assignment can beat it
+    upper_bound: Specificity,
+}
+
+impl AssignmentSearch<'_> {
+    /// returns true once the search can stop early
+    fn run(&mut self) -> bool {
+        let Some(options) = self.candidates.get(self.assignment.len()) else {
+            let specificity =
+                Specificity::new(self.assignment.iter().map(|(_, kind)| *kind).collect());
+            if self
+                .best
+                .as_ref()
+                .is_none_or(|(_, best)| specificity > *best)
+            {
+                let done = specificity == self.upper_bound;
+                let outputs = self.assignment.iter().map(|(idx, _)| *idx).collect();
+                self.best = Some((outputs, specificity));
+                return done;
+            }
+            return false;
+        };
+
+        for &(idx, kind) in options {
+            if self.used[idx] {
+                continue;
+            }
+
+            self.used[idx] = true;
+            self.assignment.push((idx, kind));
+            let done = self.run();
+            self.assignment.pop();
+            self.used[idx] = false;
+
+            if done {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+/// Index of the setting `reference` names by its `alias`, then by its `output`, given as
+/// `(alias, output)` pairs. Unlike the connector a setting matched, these are known
+/// without any outputs connected.
+pub fn find_named<'a>(
+    settings: impl IntoIterator<Item = (Option<&'a str>, &'a str)>,
+    reference: &str,
+) -> Option<usize> {
+    let settings: Vec<_> = settings.into_iter().collect();
+    settings
+        .iter()
+        .position(|(alias, _)| *alias == Some(reference))
+        .or_else(|| settings.iter().position(|(_, output)| *output == reference))
+}
+
+/// orders connectors by type regardless of case, then by number, so DP-2 comes before
+/// DP-10 and eDP-1 before HDMI-A-1
+fn connector_sort_key(name: &str) -> (String, u32, &str) {
+    let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = name[prefix.len()..].parse().unwrap_or(0);
+    (prefix.to_ascii_lowercase(), number, name)
+}
+
+impl Profile {
+    /// wlr-randr arguments applying the output settings, `None` if there are no settings
+    pub fn generate_wlr_randr_args(
+        &self,
+        output_name_map: &HashMap<String, String>,
+    ) -> Option<Vec<String>> {
         if self.settings.is_empty() {
-            commands.extend(self.exec.iter().cloned());
-            return commands;
+            return None;
         }
 
-        let mut args = vec!["wlr-randr".to_string()];
+        let mut args = Vec::new();
+        let mut push = |flag: &str, value: Option<&str>| {
+            args.push(flag.to_string());
+            args.extend(value.map(str::to_string));
+        };
 
         for setting in &self.settings {
             let output_name = output_name_map
                 .get(&setting.output)
                 .unwrap_or(&setting.output);
 
-            args.push(format!("--output '{output_name}'"));
-
-            if setting.on {
-                args.push("--on".to_string());
-            } else {
-                args.push("--off".to_string());
+            push("--output", Some(output_name));
+            match setting.on {
+                Some(true) => push("--on", None),
+                Some(false) => push("--off", None),
+                None => {}
             }
 
-            if let Some(mode) = &setting.mode {
-                args.push(format!("--mode '{mode}'"));
+            // resolved to a single advertised mode by the time a profile is applied
+            if let Some(mode) = setting.mode.as_ref().and_then(|m| m.0.first()) {
+                push("--mode", Some(mode));
             }
 
             if setting.preferred {
-                args.push("--preferred".to_string());
+                push("--preferred", None);
             }
 
             if let Some(pos) = &setting.pos {
-                args.push(format!("--pos '{pos}'"));
+                push("--pos", Some(pos));
             }
 
             if let Some(left_of) = &setting.left_of {
-                args.push(format!("--left-of '{left_of}'"));
+                push("--left-of", Some(left_of));
             }
             if let Some(right_of) = &setting.right_of {
-                args.push(format!("--right-of '{right_of}'"));
+                push("--right-of", Some(right_of));
             }
             if let Some(above) = &setting.above {
-                args.push(format!("--above '{above}'"));
+                push("--above", Some(above));
             }
             if let Some(below) = &setting.below {
-                args.push(format!("--below '{below}'"));
+                push("--below", Some(below));
             }
 
             if let Some(transform) = &setting.transform {
-                args.push(format!("--transform '{transform}'"));
+                push("--transform", Some(transform));
+            }
+
+            // an automatic scale is resolved to a fixed one by the time a profile is applied
+            if let Some(Scale::Fixed(scale)) = setting.scale {
+                push("--scale", Some(&scale.to_string()));
             }
 
-            if let Some(scale) = setting.scale {
-                args.push(format!("--scale '{scale}'"));
+            if let Some(adaptive_sync) = setting.adaptive_sync {
+                let state = if adaptive_sync { "enabled" } else { "disabled" };
+                push("--adaptive-sync", Some(state));
             }
+        }
+
+        Some(args)
+    }
 
-            if setting.adaptive_sync {
-                args.push("--adaptive-sync enabled".to_string());
-            } else {
-                args.push("--adaptive-sync disabled".to_string());
+    /// matches the profile's settings against `connected_outputs`, `None` if the
+    /// profile doesn't fit them
+    pub fn match_outputs(&self, connected_outputs: &[OutputInfo]) -> Option<ProfileMatch> {
+        let fits = match self.extra_outputs {
+            ExtraOutputs::Reject => self.settings.len() == connected_outputs.len(),
+            _ => self.settings.len() <= connected_outputs.len(),
+        };
+        if !fits {
+            return None;
+        }
+
+        let mut candidates = Vec::with_capacity(self.settings.len());
+        for setting in &self.settings {
+            let pattern = match Pattern::new(&setting.output) {
+                Ok(p) => p,
+                Err(e) => {
+                    log::error!("Invalid output pattern '{}': {e}", setting.output);
+                    return None;
+                }
+            };
+
+            let matching: Vec<(usize, MatchKind)> = connected_outputs
+                .iter()
+                .enumerate()
+                .filter_map(|(i, out)| out.match_kind(&pattern).map(|kind| (i, kind)))
+                .collect();
+
+            log::debug!(
+                "Pattern '{}' against outputs: {:?} => {:?}",
+                setting.output,
+                connected_outputs
+                    .iter()
+                    .map(|o| o.to_string())
+                    .collect::<Vec<_>>(),
+                matching
+            );
+
+            if matching.is_empty() {
+                return None;
             }
+            candidates.push(matching);
         }
 
-        commands.push(args.join(" "));
-        commands.extend(self.exec.iter().cloned());
-        commands
+        let upper_bound = Specificity::new(
+            candidates
+                .iter()
+                .filter_map(|options| options.iter().map(|(_, kind)| *kind).max())
+                .collect(),
+        );
+        let mut search = AssignmentSearch {
+            candidates: &candidates,
+            used: vec![false; connected_outputs.len()],
+            assignment: Vec::with_capacity(candidates.len()),
+            best: None,
+            upper_bound,
+        };
+        search.run();
+        let (assignment, specificity) = search.best?;
+
+        let name_map = self
+            .settings
+            .iter()
+            .zip(assignment)
+            .map(|(setting, idx)| (setting.output.clone(), connected_outputs[idx].name.clone()))
+            .collect();
+
+        Some(ProfileMatch {
+            name_map,
+            specificity,
+        })
+    }
+
+    /// Index of the setting `reference` refers to, by its `alias`, its `output` or the
+    /// connector that output matched, in this order.
+    pub fn find_setting(
+        &self,
+        reference: &str,
+        output_name_map: &HashMap<String, String>,
+    ) -> Option<usize> {
+        let settings = &self.settings;
+        find_named(
+            settings
+                .iter()
+                .map(|s| (s.alias.as_deref(), s.output.as_str())),
+            reference,
+        )
+        .or_else(|| {
+            settings
+                .iter()
+                .position(|s| output_name_map.get(&s.output).unwrap_or(&s.output) == reference)
+        })
+    }
+
+    /// Copy of the profile with `left_of`, `right_of`, `above` and `below` pointing at
+    /// connectors, resolved the same way as `output`.
+    ///
+    /// A target can be the `alias` or `output` of another setting or the connector it
+    /// matched, anything else doesn't refer to an output of the profile and is an error.
+    pub fn resolve_relative_targets(
+        &self,
+        output_name_map: &HashMap<String, String>,
+    ) -> Result<Profile> {
+        let resolve = |target: &mut Option<String>| -> Result<()> {
+            let Some(target) = target else {
+                return Ok(());
+            };
+            let idx = self
+                .find_setting(target, output_name_map)
+                .with_context(|| {
+                    format!("Relative position target '{target}' is not an output of the profile")
+                })?;
+            let output = &self.settings[idx].output;
+            *target = output_name_map.get(output).unwrap_or(output).clone();
+            Ok(())
+        };
+
+        let mut profile = self.clone();
+        for setting in &mut profile.settings {
+            resolve(&mut setting.left_of)?;
+            resolve(&mut setting.right_of)?;
+            resolve(&mut setting.above)?;
+            resolve(&mut setting.below)?;
+        }
+        Ok(profile)
+    }
+
+    /// Copy of the profile with settings for connected outputs it doesn't mention,
+    /// according to its `extra_outputs` policy.
+    ///
+    /// Extra outputs turned on are chained to the right of the rightmost output of the
+    /// profile, so its positions have to be resolved already, see
+    /// [`crate::layout::resolve_positions`].
+    pub fn with_extra_outputs(
+        &self,
+        connected_outputs: &[OutputInfo],
+        output_name_map: &HashMap<String, String>,
+    ) -> Result<Profile> {
+        let mut profile = self.clone();
+        if matches!(
+            self.extra_outputs,
+            ExtraOutputs::Reject | ExtraOutputs::Keep
+        ) {
+            return Ok(profile);
+        }
+
+        let mut previous = None;
+        let mut start = Position::default();
+        if self.extra_outputs == ExtraOutputs::Auto {
+            let planned = plan_configuration(connected_outputs, &self.settings, output_name_map)?;
+            let rightmost = planned
+                .iter()
+                .filter(|o| o.enabled && output_name_map.values().any(|name| name == &o.name))
+                .max_by_key(|o| o.position.x + o.logical_size().0);
+            if let Some(output) = rightmost {
+                start = Position {
+                    x: output.position.x + output.logical_size().0,
+                    y: output.position.y,
+                };
+            }
+        }
+
+        // sorted by connector, backends and hotplugs don't agree on an order
+        let mut extras: Vec<&OutputInfo> = connected_outputs
+            .iter()
+            .filter(|o| !output_name_map.values().any(|name| name == &o.name))
+            .collect();
+        extras.sort_by_key(|o| connector_sort_key(&o.name));
+
+        for output in extras {
+            let setting = match self.extra_outputs {
+                ExtraOutputs::Off => OutputSetting {
+                    output: output.name.clone(),
+                    on: Some(false),
+                    ..Default::default()
+                },
+                _ => {
+                    let right_of = previous.replace(output.name.clone());
+                    OutputSetting {
+                        output: output.name.clone(),
+                        on: Some(true),
+                        preferred: true,
+                        pos: right_of
+                            .is_none()
+                            .then(|| format!("{},{}", start.x, start.y)),
+                        right_of,
+                        ..Default::default()
+                    }
+                }
+            };
+            profile.settings.push(setting);
+        }
+
+        Ok(profile)
     }
 }
 
+/// `$XDG_CONFIG_HOME/auto-wlr-randr/config.toml`, falling back to `~/.config`
+pub fn default_config_path() -> PathBuf {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".config")
+        });
+    config_dir.join("auto-wlr-randr").join("config.toml")
+}
+
 impl Config {
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
@@ -146,122 +757,251 @@ impl Config {
         let mut config: Config = toml::from_str(&content)
             .with_context(|| format!("Failed to parse config file at {path:?}"))?;
 
+        if config.legacy_output_defaults {
+            let settings = config
+                .profiles
+                .values_mut()
+                .flat_map(|profile| &mut profile.settings);
+            for setting in settings {
+                setting.on.get_or_insert(false);
+                setting.adaptive_sync.get_or_insert(false);
+            }
+        }
+
         config.config_path = path.to_string_lossy().to_string();
         Ok(config)
     }
 
+    pub fn path(&self) -> &Path {
+        Path::new(&self.config_path)
+    }
+
     pub fn reload_config(&mut self) -> Result<()> {
         *self = Self::load_from_file(&self.config_path)?;
         Ok(())
     }
 
+    /// Every profile matching `connected_outputs`, best first: the highest priority, then
+    /// the most specific match, ties in the order the profiles are defined in.
+    pub fn matching_profiles(
+        &self,
+        connected_outputs: &[OutputInfo],
+    ) -> Vec<(&str, &Profile, HashMap<String, String>)> {
+        let mut matches: Vec<(&str, &Profile, ProfileMatch)> = self
+            .profiles
+            .iter()
+            .filter_map(|(profile_id, profile)| {
+                let matched = profile.match_outputs(connected_outputs)?;
+                Some((profile_id.as_str(), profile, matched))
+            })
+            .collect();
+
+        // stable, so ties keep the file order
+        matches.sort_by(|(_, a, a_match), (_, b, b_match)| {
+            (b.priority, &b_match.specificity).cmp(&(a.priority, &a_match.specificity))
+        });
+        matches
+            .into_iter()
+            .map(|(profile_id, profile, matched)| (profile_id, profile, matched.name_map))
+            .collect()
+    }
+
     pub fn find_matching_profile(
         &self,
         connected_outputs: &[OutputInfo],
     ) -> Option<(&str, &Profile, HashMap<String, String>)> {
-        'profile_loop: for (profile_id, profile) in &self.profiles {
-            if profile.settings.len() != connected_outputs.len() {
-                continue;
-            }
-
-            if profile.settings.is_empty() {
-                return if connected_outputs.is_empty() {
-                    Some((profile_id, profile, HashMap::new()))
-                } else {
-                    None
-                };
-            }
-
-            let mut used_outputs = vec![false; connected_outputs.len()];
-            let mut output_name_map = HashMap::with_capacity(profile.settings.len());
-
-            for setting in &profile.settings {
-                let pattern = match Pattern::new(&setting.output) {
-                    Ok(p) => p,
-                    Err(e) => {
-                        log::error!("Invalid output pattern '{}': {e}", setting.output);
-                        continue 'profile_loop;
-                    }
-                };
-
-                let found = connected_outputs
-                    .iter()
-                    .enumerate()
-                    .find(|(i, out)| !used_outputs[*i] && out.matches_pattern(&pattern));
-
-                log::debug!(
-                    "Pattern '{}' against outputs: {:?} => {:?}",
-                    setting.output,
-                    connected_outputs
-                        .iter()
-                        .map(|o| o.to_string())
-                        .collect::<Vec<_>>(),
-                    found.map(|(i, _)| i)
-                );
-
-                if let Some((idx, matched)) = found {
-                    used_outputs[idx] = true;
-                    output_name_map.insert(setting.output.clone(), matched.name.clone());
-                } else {
-                    continue 'profile_loop;
-                }
-            }
-
-            return Some((profile_id, profile, output_name_map));
-        }
-
-        None
+        self.matching_profiles(connected_outputs).into_iter().next()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::output::{Mode, PhysicalSize};
+
+    fn make_panel(name: &str, size: (i32, i32), mm: (i32, i32)) -> OutputInfo {
+        OutputInfo {
+            name: name.into(),
+            physical_size: PhysicalSize {
+                width: mm.0,
+                height: mm.1,
+            },
+            modes: vec![Mode {
+                width: size.0,
+                height: size.1,
+                refresh: 60.0,
+                preferred: true,
+                current: true,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_auto_scale() {
+        let auto = AutoScale::default();
+        // 14" 2.8K laptop panel and a 27" 1440p monitor
+        let laptop = make_panel("eDP-1", (2880, 1800), (302, 189));
+        let monitor = make_panel("DP-1", (2560, 1440), (597, 336));
+        assert_eq!(auto.scale_for(&laptop), Some(1.75));
+        assert_eq!(auto.scale_for(&monitor), Some(1.0));
+
+        // 27" 4K monitor
+        let monitor = make_panel("DP-2", (3840, 2160), (597, 336));
+        assert_eq!(auto.scale_for(&monitor), Some(1.5));
+
+        let auto = AutoScale {
+            dpi: Some(96.0),
+            step: Some(0.125),
+        };
+        assert_eq!(auto.scale_for(&monitor), Some(1.75));
+
+        let projector = make_panel("HDMI-A-1", (1920, 1080), (0, 0));
+        assert_eq!(auto.scale_for(&projector), None);
+    }
 
     #[test]
-    fn test_generate_commands_with_settings() {
+    fn test_generate_wlr_randr_args_empty_settings() {
         let profile = Profile {
-            exec: vec!["echo 'done'".into()],
+            exec: vec!["echo 'test'".into()],
+            settings: vec![],
+            ..Default::default()
+        };
+
+        assert_eq!(profile.generate_wlr_randr_args(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn test_with_extra_outputs() {
+        let outputs: Vec<OutputInfo> = ["eDP-1", "HDMI-A-1", "DP-10", "DP-2"]
+            .iter()
+            .map(|name| OutputInfo {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let mut name_map = HashMap::new();
+        name_map.insert("eDP*".to_string(), "eDP-1".to_string());
+
+        let mut profile = Profile {
             settings: vec![OutputSetting {
-                output: "HDMI-1".into(),
-                on: true,
-                mode: Some("1920x1080".into()),
-                preferred: false,
-                pos: Some("0,0".into()),
-                left_of: None,
-                right_of: None,
-                above: None,
-                below: None,
-                transform: None,
-                scale: Some(1.0),
-                adaptive_sync: true,
+                output: "eDP*".into(),
+                on: Some(true),
+                ..Default::default()
             }],
+            ..Default::default()
         };
 
-        let mut name_map = HashMap::new();
-        name_map.insert("HDMI-1".to_string(), "HDMI-A-1".to_string());
-
-        let commands = profile.generate_commands(&name_map);
-
-        assert_eq!(commands.len(), 2);
-        assert!(commands[0].starts_with("wlr-randr"));
-        assert!(commands[0].contains("--output 'HDMI-A-1'"));
-        assert!(commands[0].contains("--on"));
-        assert!(commands[0].contains("--mode '1920x1080'"));
-        assert!(commands[0].contains("--scale '1'"));
-        assert!(commands[0].contains("--adaptive-sync enabled"));
-        assert_eq!(commands[1], "echo 'done'");
+        profile.extra_outputs = ExtraOutputs::Keep;
+        assert_eq!(
+            profile
+                .with_extra_outputs(&outputs, &name_map)
+                .unwrap()
+                .settings
+                .len(),
+            1
+        );
+
+        profile.extra_outputs = ExtraOutputs::Off;
+        let settings = profile
+            .with_extra_outputs(&outputs, &name_map)
+            .unwrap()
+            .settings;
+        let names: Vec<&str> = settings.iter().map(|s| s.output.as_str()).collect();
+        assert_eq!(names, ["eDP*", "DP-2", "DP-10", "HDMI-A-1"]);
+        assert!(settings[1..].iter().all(|s| s.on == Some(false)));
+
+        profile.extra_outputs = ExtraOutputs::Auto;
+        let settings = profile
+            .with_extra_outputs(&outputs, &name_map)
+            .unwrap()
+            .settings;
+        assert!(settings[1].on == Some(true) && settings[1].preferred);
+        // right of eDP-1, which has no mode and so no size
+        assert_eq!(settings[1].pos.as_deref(), Some("0,0"));
+        assert_eq!(settings[1].right_of, None);
+        assert_eq!(settings[2].right_of.as_deref(), Some("DP-2"));
+        assert_eq!(settings[3].right_of.as_deref(), Some("DP-10"));
+        assert_eq!(settings[3].pos, None);
     }
 
     #[test]
-    fn test_generate_commands_empty_settings() {
+    fn test_extra_outputs_right_of_rightmost_output() {
+        let outputs = vec![
+            make_panel("DP-1", (1920, 1080), (0, 0)),
+            make_panel("eDP-1", (2560, 1600), (0, 0)),
+            make_panel("HDMI-A-1", (1280, 720), (0, 0)),
+        ];
+        let name_map = HashMap::from([
+            ("DP-1".to_string(), "DP-1".to_string()),
+            ("eDP-1".to_string(), "eDP-1".to_string()),
+        ]);
+        // the last setting isn't the rightmost output
         let profile = Profile {
-            exec: vec!["echo 'test'".into()],
-            settings: vec![],
+            extra_outputs: ExtraOutputs::Auto,
+            settings: vec![
+                OutputSetting {
+                    output: "DP-1".into(),
+                    on: Some(true),
+                    pos: Some("2560,100".into()),
+                    ..Default::default()
+                },
+                OutputSetting {
+                    output: "eDP-1".into(),
+                    on: Some(true),
+                    pos: Some("0,0".into()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let settings = profile
+            .with_extra_outputs(&outputs, &name_map)
+            .unwrap()
+            .settings;
+        assert_eq!(settings[2].output, "HDMI-A-1");
+        assert_eq!(settings[2].pos.as_deref(), Some("4480,100"));
+        assert_eq!(settings[2].right_of, None);
+    }
+
+    #[test]
+    fn test_fallback_auto_layout() {
+        let outputs: Vec<OutputInfo> = ["HDMI-A-1", "eDP-1"]
+            .iter()
+            .map(|name| OutputInfo {
+                name: name.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let fallback = Fallback {
+            layout: FallbackLayout::Auto,
+            exec: vec!["notify-send 'unknown outputs'".into()],
+            ..Default::default()
         };
 
-        let commands = profile.generate_commands(&HashMap::new());
-        assert_eq!(commands.len(), 1);
-        assert_eq!(commands[0], "echo 'test'");
+        let profile = fallback
+            .to_profile()
+            .with_extra_outputs(&outputs, &HashMap::new())
+            .unwrap();
+
+        assert_eq!(profile.exec.len(), 1);
+        assert_eq!(profile.settings.len(), 2);
+        assert_eq!(profile.settings[0].output, "eDP-1");
+        assert_eq!(profile.settings[0].pos.as_deref(), Some("0,0"));
+        assert_eq!(profile.settings[0].right_of, None);
+        assert_eq!(profile.settings[1].right_of.as_deref(), Some("eDP-1"));
+        assert!(
+            profile
+                .settings
+                .iter()
+                .all(|s| s.on == Some(true) && s.preferred)
+        );
+
+        let profile = Fallback::default()
+            .to_profile()
+            .with_extra_outputs(&outputs, &HashMap::new())
+            .unwrap();
+        assert!(profile.settings.is_empty());
     }
 }
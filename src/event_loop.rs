@@ -1,6 +1,9 @@
+use crate::backend::BackendKind;
+use crate::children::SigchldFd;
 use crate::config::Config;
 use crate::ipc::Command;
 use crate::ipc::{self, SocketListener};
+use crate::watcher::ConfigWatcher;
 use crate::wayland;
 use crate::wayland::WaylandState;
 use anyhow::Result;
@@ -10,6 +13,8 @@ use std::os::unix::io::{AsFd, AsRawFd};
 
 const WAYLAND_EVENT: Token = Token(0);
 const IPC_EVENT: Token = Token(1);
+const CONFIG_EVENT: Token = Token(2);
+const CHILD_EVENT: Token = Token(3);
 
 pub fn handle_command(command: Command, state: &mut WaylandState) -> Result<String> {
     match command {
@@ -24,19 +29,34 @@ pub fn handle_command(command: Command, state: &mut WaylandState) -> Result<Stri
             let json_output = serde_json::json!({
                 "active_profile": state.active_profile_id.as_deref().unwrap_or("None"),
                 "connected_outputs": state.outputs.iter().map(|o| o.to_string()).collect::<Vec<_>>(),
+                "matching_profiles": state.matching_profile_ids(),
+                "last_apply": state.last_apply,
+                "awaiting_confirmation": state.awaiting_confirmation(),
+                "pinned": state.pin,
+                "locked": state.locked,
             });
             Ok(serde_json::to_string_pretty(&json_output)?)
         }
-        Command::Switch(profile_name) => {
-            log::info!("Switching to profile: {profile_name}");
-            state.apply_profile_by_name(&profile_name)?;
-            Ok(format!("Profile switched successfully to {profile_name}"))
+        Command::Switch(profile_name, options) => {
+            log::info!("Switching to profile: {profile_name} ({options:?})");
+            state.switch_profile(&profile_name, &options)
+        }
+        Command::Confirm => state.confirm(),
+        Command::Next => state.cycle_profile(true),
+        Command::Prev => state.cycle_profile(false),
+        Command::Lock => Ok(state.set_locked(true)),
+        Command::Unlock => Ok(state.set_locked(false)),
+        Command::Test(profile_name) => {
+            log::info!("Testing profile: {profile_name}");
+            state.test_profile_by_name(&profile_name)
         }
     }
 }
 
-pub fn start_event_loop(config: Config) -> Result<()> {
-    let (conn, mut state, mut event_queue) = wayland::init_wayland(config)?;
+pub fn start_event_loop(config: Config, backend_kind: BackendKind) -> Result<()> {
+    // before anything gets spawned, so no exit goes unnoticed
+    let sigchld = SigchldFd::new()?;
+    let (conn, mut state, mut event_queue) = wayland::init_wayland(config, backend_kind)?;
     let wayland_fd = conn.as_fd();
 
     log::info!("Wayland event loop initialized, starting IPC server...");
@@ -56,10 +76,38 @@ pub fn start_event_loop(config: Config) -> Result<()> {
     poll.registry()
         .register(&mut listener.listener, IPC_EVENT, Interest::READABLE)?;
 
+    poll.registry().register(
+        &mut SourceFd(&sigchld.as_raw_fd()),
+        CHILD_EVENT,
+        Interest::READABLE,
+    )?;
+
+    let mut watcher = match ConfigWatcher::new(state.config.path()) {
+        Ok(watcher) => {
+            poll.registry().register(
+                &mut SourceFd(&watcher.as_raw_fd()),
+                CONFIG_EVENT,
+                Interest::READABLE,
+            )?;
+            Some(watcher)
+        }
+        Err(e) => {
+            log::warn!("Not watching the config file for changes: {e:#}");
+            None
+        }
+    };
+
     log::info!("Event loop started, waiting for events...");
 
     loop {
-        poll.poll(&mut events, None)?;
+        let timeout = [
+            watcher.as_ref().and_then(ConfigWatcher::timeout),
+            state.confirmation_timeout(),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        poll.poll(&mut events, timeout)?;
         for event in events.iter() {
             match event.token() {
                 WAYLAND_EVENT => {
@@ -86,8 +134,33 @@ pub fn start_event_loop(config: Config) -> Result<()> {
                         }
                     }
                 }
+                CONFIG_EVENT => {
+                    if let Some(watcher) = watcher.as_mut()
+                        && let Err(e) = watcher.handle_events()
+                    {
+                        log::error!("Error watching the config file: {e:#}");
+                    }
+                }
+                CHILD_EVENT => {
+                    if let Err(e) = sigchld.drain() {
+                        log::error!("{e:#}");
+                    }
+                    state.children.reap();
+                }
                 _ => unreachable!(),
             }
         }
+
+        state.revert_unconfirmed();
+
+        if let Some(watcher) = watcher.as_mut()
+            && watcher.take_due()
+        {
+            log::info!("Config file changed, reloading...");
+            match state.config.reload_config() {
+                Ok(()) => state.evaluate_profiles(true),
+                Err(e) => log::error!("Keeping the previous configuration: {e:#}"),
+            }
+        }
     }
 }
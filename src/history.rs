@@ -0,0 +1,92 @@
+use crate::output::OutputInfo;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `$XDG_STATE_HOME/auto-wlr-randr/last-profiles.json`, falling back to `~/.local/state`
+pub fn default_history_path() -> PathBuf {
+    let state_dir = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| {
+            PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".local/state")
+        });
+    state_dir.join("auto-wlr-randr").join("last-profiles.json")
+}
+
+/// Identifies a set of connected outputs by their make, model and serial, or their name
+/// when they don't report those. Doesn't depend on the order the outputs are reported in.
+pub fn fingerprint(outputs: &[OutputInfo]) -> String {
+    let mut ids: Vec<String> = outputs
+        .iter()
+        .map(|o| o.build_identifier().unwrap_or_else(|| o.name.clone()))
+        .collect();
+    ids.sort();
+    ids.join(", ")
+}
+
+/// Profile chosen by hand for each set of connected outputs, kept across restarts.
+#[derive(Debug, Default)]
+pub struct ProfileHistory {
+    /// `None` keeps the history in memory only
+    path: Option<PathBuf>,
+    profiles: BTreeMap<String, String>,
+}
+
+impl ProfileHistory {
+    /// Loads the history at `path`, starting empty when there is none or it can't be read.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let profiles = match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable profile history at {path:?}: {e}");
+                BTreeMap::new()
+            }),
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("Failed to read profile history at {path:?}: {e}");
+                }
+                BTreeMap::new()
+            }
+        };
+
+        Self {
+            path: Some(path),
+            profiles,
+        }
+    }
+
+    /// profile last chosen for `outputs`
+    pub fn get(&self, outputs: &[OutputInfo]) -> Option<&str> {
+        self.profiles.get(&fingerprint(outputs)).map(String::as_str)
+    }
+
+    /// Remembers `profile_id` for `outputs` and writes the history out.
+    pub fn record(&mut self, outputs: &[OutputInfo], profile_id: &str) -> Result<()> {
+        let previous = self
+            .profiles
+            .insert(fingerprint(outputs), profile_id.to_string());
+        if previous.as_deref() == Some(profile_id) {
+            return Ok(());
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create state directory {dir:?}"))?;
+        }
+
+        // written next to it and renamed over it, so a crash can't leave half a file
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_vec_pretty(&self.profiles)?)
+            .with_context(|| format!("Failed to write profile history to {temp:?}"))?;
+        fs::rename(&temp, path)
+            .with_context(|| format!("Failed to write profile history to {path:?}"))
+    }
+}
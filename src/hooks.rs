@@ -0,0 +1,166 @@
+use crate::output::OutputInfo;
+use std::collections::HashMap;
+use std::process::Command;
+
+pub const ENV_PROFILE: &str = "AUTO_WLR_RANDR_PROFILE";
+pub const ENV_PREVIOUS_PROFILE: &str = "AUTO_WLR_RANDR_PREVIOUS_PROFILE";
+pub const ENV_NAME_MAP: &str = "AUTO_WLR_RANDR_NAME_MAP";
+pub const ENV_OUTPUTS: &str = "AUTO_WLR_RANDR_OUTPUTS";
+
+/// What hooks get to know about the profile activation that triggered them.
+#[derive(Debug, Clone, Default)]
+pub struct HookContext {
+    pub profile: String,
+    pub previous_profile: Option<String>,
+    /// output pattern of a setting to the connector it matched
+    pub name_map: HashMap<String, String>,
+    pub outputs: Vec<OutputInfo>,
+}
+
+/// single-quotes `value` so the shell takes it as one word, whatever it contains
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+impl HookContext {
+    fn output_field(&self, placeholder: &str) -> Option<String> {
+        let (pattern, field) = placeholder.rsplit_once('.')?;
+        let name = self.name_map.get(pattern)?;
+        let output = self.outputs.iter().find(|o| &o.name == name)?;
+
+        let value = match field {
+            "name" => output.name.clone(),
+            "make" => output.make.clone().unwrap_or_default(),
+            "model" => output.model.clone().unwrap_or_default(),
+            "serial" => output.serial.clone().unwrap_or_default(),
+            "mode" => output
+                .current_mode()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            "pos" => format!("{},{}", output.position.x, output.position.y),
+            "scale" => output.scale.to_string(),
+            "transform" => output.transform.to_string(),
+            _ => {
+                log::warn!("Unknown output field '{field}' in '{{{placeholder}}}'");
+                return None;
+            }
+        };
+        Some(value)
+    }
+
+    /// Replaces `{PATTERN.FIELD}` placeholders, where `PATTERN` is the `output` of one of
+    /// the profile's settings, with the shell-quoted value from the output it matched.
+    ///
+    /// Anything else in braces is left alone, so shell syntax like `${HOME}` keeps working.
+    pub fn expand(&self, command: &str) -> String {
+        let mut expanded = String::with_capacity(command.len());
+        let mut rest = command;
+
+        while let Some(start) = rest.find('{') {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let Some(end) = rest.find('}') else { break };
+            let placeholder = &rest[1..end];
+            match self.output_field(placeholder) {
+                Some(value) => expanded.push_str(&shell_quote(&value)),
+                None => expanded.push_str(&rest[..=end]),
+            }
+            rest = &rest[end + 1..];
+        }
+
+        expanded.push_str(rest);
+        expanded
+    }
+
+    /// environment exported to every command of the profile
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (ENV_PROFILE, self.profile.clone()),
+            (
+                ENV_PREVIOUS_PROFILE,
+                self.previous_profile.clone().unwrap_or_default(),
+            ),
+            (
+                ENV_NAME_MAP,
+                serde_json::to_string(&self.name_map).unwrap_or_default(),
+            ),
+            (
+                ENV_OUTPUTS,
+                serde_json::to_string(&self.outputs).unwrap_or_default(),
+            ),
+        ]
+    }
+
+    /// `sh -c` running `command` with placeholders expanded and the environment set
+    pub fn command(&self, command: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(self.expand(command)).envs(self.env());
+        cmd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Mode;
+
+    fn make_context() -> HookContext {
+        HookContext {
+            profile: "docked".into(),
+            previous_profile: Some("laptop".into()),
+            name_map: HashMap::from([
+                ("Dell Inc. U2718Q".to_string(), "DP-3".to_string()),
+                ("eDP-1".to_string(), "eDP-1".to_string()),
+            ]),
+            outputs: vec![
+                OutputInfo {
+                    name: "eDP-1".into(),
+                    ..Default::default()
+                },
+                OutputInfo {
+                    name: "DP-3".into(),
+                    make: Some("Dell Inc.".into()),
+                    model: Some("U2718Q".into()),
+                    modes: vec![Mode {
+                        width: 3840,
+                        height: 2160,
+                        refresh: 60.0,
+                        preferred: true,
+                        current: true,
+                    }],
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_expand_placeholders() {
+        let context = make_context();
+
+        assert_eq!(
+            context.expand("swaybg -o {Dell Inc. U2718Q.name} -i ~/{Dell Inc. U2718Q.mode}.png"),
+            "swaybg -o 'DP-3' -i ~/'3840x2160@60Hz'.png"
+        );
+        assert_eq!(
+            context.expand("notify-send {Dell Inc. U2718Q.make}"),
+            "notify-send 'Dell Inc.'"
+        );
+        assert_eq!(
+            context.expand("echo ${HOME} {HDMI-1.name} {eDP-1.size} {eDP-1.name"),
+            "echo ${HOME} {HDMI-1.name} {eDP-1.size} {eDP-1.name"
+        );
+    }
+
+    #[test]
+    fn test_expand_quotes_values() {
+        let mut context = make_context();
+        context.outputs[1].model = Some("it's; rm -rf ~".into());
+
+        assert_eq!(
+            context.expand("echo {Dell Inc. U2718Q.model}"),
+            r"echo 'it'\''s; rm -rf ~'"
+        );
+    }
+}
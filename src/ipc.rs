@@ -4,12 +4,30 @@ use mio::net::UnixListener;
 use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// how a manual switch to a profile behaves
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SwitchOptions {
+    /// switch back unless `Confirm` arrives within this time
+    pub confirm: Option<Duration>,
+    /// keep the profile even when the connected outputs change
+    pub sticky: bool,
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum Command {
     Reload,
     Status,
-    Switch(String),
+    Switch(String, SwitchOptions),
+    Confirm,
+    /// switch to the next or previous profile matching the connected outputs
+    Next,
+    Prev,
+    /// suspend automatic profile matching
+    Lock,
+    Unlock,
+    Test(String),
 }
 
 pub struct SocketListener {
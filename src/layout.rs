@@ -0,0 +1,271 @@
+use crate::backend::plan_configuration;
+use crate::config::{LayoutAlign, LayoutDirection, OutputSetting, Profile};
+use crate::output::OutputInfo;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+fn set_position(setting: &mut OutputSetting, x: i32, y: i32) {
+    setting.pos = Some(format!("{x},{y}"));
+    setting.left_of = None;
+    setting.right_of = None;
+    setting.above = None;
+    setting.below = None;
+}
+
+fn has_relative_target(setting: &OutputSetting) -> bool {
+    setting.left_of.is_some()
+        || setting.right_of.is_some()
+        || setting.above.is_some()
+        || setting.below.is_some()
+}
+
+fn place_layout(
+    profile: &mut Profile,
+    outputs: &[OutputInfo],
+    name_map: &HashMap<String, String>,
+) -> Result<()> {
+    let planned = plan_configuration(outputs, &profile.settings, name_map)?;
+
+    let mut placed: Vec<(usize, (i32, i32))> = Vec::with_capacity(profile.layout.len());
+    for reference in &profile.layout {
+        let idx = profile.find_setting(reference, name_map).with_context(|| {
+            format!("Layout entry '{reference}' is not an output of the profile")
+        })?;
+        if placed.iter().any(|(placed_idx, _)| *placed_idx == idx) {
+            anyhow::bail!("Layout entry '{reference}' refers to an output placed already");
+        }
+
+        let setting = &profile.settings[idx];
+        let name = name_map.get(&setting.output).unwrap_or(&setting.output);
+        let output = planned
+            .iter()
+            .find(|o| &o.name == name)
+            .with_context(|| format!("Output '{name}' is not connected"))?;
+        if output.enabled {
+            placed.push((idx, output.logical_size()));
+        }
+    }
+
+    // (along the direction, across it)
+    let extent = |(width, height): (i32, i32)| match profile.layout_direction {
+        LayoutDirection::Horizontal => (width, height),
+        LayoutDirection::Vertical => (height, width),
+    };
+    let cross_extent = placed
+        .iter()
+        .map(|(_, size)| extent(*size).1)
+        .max()
+        .unwrap_or(0);
+
+    let mut cursor = 0;
+    for (idx, size) in placed {
+        let (main, cross) = extent(size);
+        let offset = match profile.layout_align {
+            LayoutAlign::Top => 0,
+            LayoutAlign::Center => (cross_extent - cross) / 2,
+            LayoutAlign::Bottom => cross_extent - cross,
+        };
+        let (x, y) = match profile.layout_direction {
+            LayoutDirection::Horizontal => (cursor, offset),
+            LayoutDirection::Vertical => (offset, cursor),
+        };
+        set_position(&mut profile.settings[idx], x, y);
+        cursor += main;
+    }
+
+    Ok(())
+}
+
+/// Copy of `profile` where outputs placed by `layout` or a relative position target get
+/// a plain `pos` instead, computed from the mode, scale and transform each output ends
+/// up with.
+///
+/// Relative targets have to point at connectors already, see
+/// [`Profile::resolve_relative_targets`].
+pub fn resolve_positions(
+    profile: &Profile,
+    outputs: &[OutputInfo],
+    name_map: &HashMap<String, String>,
+) -> Result<Profile> {
+    let mut profile = profile.clone();
+    if !profile.layout.is_empty() {
+        place_layout(&mut profile, outputs, name_map)?;
+    }
+
+    if !profile.settings.iter().any(has_relative_target) {
+        return Ok(profile);
+    }
+
+    // planned after the layout, so outputs can be placed relative to the ones in it
+    let planned = plan_configuration(outputs, &profile.settings, name_map)?;
+    for setting in &mut profile.settings {
+        if !has_relative_target(setting) {
+            continue;
+        }
+
+        let name = name_map.get(&setting.output).unwrap_or(&setting.output);
+        if let Some(output) = planned.iter().find(|o| &o.name == name)
+            && output.enabled
+        {
+            let position = output.position;
+            set_position(setting, position.x, position.y);
+        }
+    }
+
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Scale;
+    use crate::output::Mode;
+
+    fn make_output(name: &str, width: i32, height: i32) -> OutputInfo {
+        OutputInfo {
+            name: name.to_string(),
+            enabled: true,
+            modes: vec![Mode {
+                width,
+                height,
+                refresh: 60.0,
+                preferred: true,
+                current: true,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn make_setting(output: &str, alias: &str) -> OutputSetting {
+        OutputSetting {
+            output: output.into(),
+            alias: Some(alias.into()),
+            on: Some(true),
+            ..Default::default()
+        }
+    }
+
+    fn positions(profile: &Profile) -> Vec<Option<&str>> {
+        profile.settings.iter().map(|s| s.pos.as_deref()).collect()
+    }
+
+    #[test]
+    fn test_layout_horizontal_with_scale_and_transform() {
+        let outputs = vec![
+            make_output("eDP-1", 2880, 1800),
+            make_output("DP-1", 2560, 1440),
+            make_output("DP-2", 2560, 1440),
+        ];
+        let name_map = HashMap::from([
+            ("Dell*A".to_string(), "DP-1".to_string()),
+            ("Dell*B".to_string(), "DP-2".to_string()),
+        ]);
+        let profile = Profile {
+            layout: vec!["left-dell".into(), "laptop".into(), "DP-2".into()],
+            settings: vec![
+                OutputSetting {
+                    scale: Some(Scale::Fixed(2.0)),
+                    ..make_setting("eDP-1", "laptop")
+                },
+                make_setting("Dell*A", "left-dell"),
+                OutputSetting {
+                    transform: Some("90".into()),
+                    ..make_setting("Dell*B", "right-dell")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let resolved = resolve_positions(&profile, &outputs, &name_map).unwrap();
+        assert_eq!(
+            positions(&resolved),
+            vec![Some("2560,0"), Some("0,0"), Some("4000,0")]
+        );
+
+        let profile = Profile {
+            layout_align: LayoutAlign::Center,
+            ..profile
+        };
+        let resolved = resolve_positions(&profile, &outputs, &name_map).unwrap();
+        // the rotated output is 2560 high, the others are centered next to it
+        assert_eq!(
+            positions(&resolved),
+            vec![Some("2560,830"), Some("0,560"), Some("4000,0")]
+        );
+    }
+
+    #[test]
+    fn test_layout_vertical_bottom() {
+        let outputs = vec![
+            make_output("eDP-1", 1920, 1080),
+            make_output("DP-1", 2560, 1440),
+        ];
+        let profile = Profile {
+            layout: vec!["DP-1".into(), "eDP-1".into()],
+            layout_direction: LayoutDirection::Vertical,
+            layout_align: LayoutAlign::Bottom,
+            settings: vec![
+                make_setting("eDP-1", "laptop"),
+                make_setting("DP-1", "dell"),
+            ],
+            ..Default::default()
+        };
+
+        let resolved = resolve_positions(&profile, &outputs, &HashMap::new()).unwrap();
+        assert_eq!(positions(&resolved), vec![Some("640,1440"), Some("0,0")]);
+    }
+
+    #[test]
+    fn test_layout_skips_disabled_and_rejects_unknown() {
+        let outputs = vec![
+            make_output("eDP-1", 1920, 1080),
+            make_output("DP-1", 2560, 1440),
+        ];
+        let mut profile = Profile {
+            layout: vec!["laptop".into(), "dell".into()],
+            settings: vec![
+                OutputSetting {
+                    on: Some(false),
+                    ..make_setting("eDP-1", "laptop")
+                },
+                make_setting("DP-1", "dell"),
+            ],
+            ..Default::default()
+        };
+
+        let resolved = resolve_positions(&profile, &outputs, &HashMap::new()).unwrap();
+        assert_eq!(positions(&resolved), vec![None, Some("0,0")]);
+
+        profile.layout.push("HDMI-A-1".into());
+        assert!(resolve_positions(&profile, &outputs, &HashMap::new()).is_err());
+
+        profile.layout = vec!["dell".into(), "DP-1".into()];
+        assert!(resolve_positions(&profile, &outputs, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_relative_targets_become_positions() {
+        let outputs = vec![
+            make_output("eDP-1", 2880, 1800),
+            make_output("DP-1", 2560, 1440),
+        ];
+        let profile = Profile {
+            settings: vec![
+                OutputSetting {
+                    scale: Some(Scale::Fixed(2.0)),
+                    pos: Some("0,0".into()),
+                    ..make_setting("eDP-1", "laptop")
+                },
+                OutputSetting {
+                    right_of: Some("eDP-1".into()),
+                    ..make_setting("DP-1", "dell")
+                },
+            ],
+            ..Default::default()
+        };
+
+        let resolved = resolve_positions(&profile, &outputs, &HashMap::new()).unwrap();
+        assert_eq!(positions(&resolved), vec![Some("0,0"), Some("1440,0")]);
+        assert_eq!(resolved.settings[1].right_of, None);
+    }
+}
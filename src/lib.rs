@@ -1,5 +1,12 @@
+pub mod backend;
+pub mod check;
+pub mod children;
 pub mod config;
 pub mod event_loop;
+pub mod history;
+pub mod hooks;
 pub mod ipc;
+pub mod layout;
 pub mod output;
+pub mod watcher;
 pub mod wayland;
@@ -1,15 +1,340 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use glob::Pattern;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fmt;
-use std::process::Command;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mode {
+    pub width: i32,
+    pub height: i32,
+    /// refresh rate in Hz, 0 if unknown
+    #[serde(default)]
+    pub refresh: f64,
+    #[serde(default)]
+    pub preferred: bool,
+    #[serde(default)]
+    pub current: bool,
+}
+
+/// formatted the way the config accepts it, `WIDTHxHEIGHT[@RATEHz]`
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        if self.refresh > 0.0 {
+            write!(f, "@{}Hz", self.refresh)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl FromStr for Position {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid position '{s}', expected X,Y");
+        let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+        Ok(Self {
+            x: x.trim().parse().map_err(|_| invalid())?,
+            y: y.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// mirrors wl_output.transform, using wlr-randr's names
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transform {
+    #[default]
+    #[serde(rename = "normal")]
+    Normal,
+    #[serde(rename = "90")]
+    Rotate90,
+    #[serde(rename = "180")]
+    Rotate180,
+    #[serde(rename = "270")]
+    Rotate270,
+    #[serde(rename = "flipped")]
+    Flipped,
+    #[serde(rename = "flipped-90")]
+    Flipped90,
+    #[serde(rename = "flipped-180")]
+    Flipped180,
+    #[serde(rename = "flipped-270")]
+    Flipped270,
+}
+
+impl Transform {
+    pub const ALL: [Transform; 8] = [
+        Transform::Normal,
+        Transform::Rotate90,
+        Transform::Rotate180,
+        Transform::Rotate270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Transform::Normal => "normal",
+            Transform::Rotate90 => "90",
+            Transform::Rotate180 => "180",
+            Transform::Rotate270 => "270",
+            Transform::Flipped => "flipped",
+            Transform::Flipped90 => "flipped-90",
+            Transform::Flipped180 => "flipped-180",
+            Transform::Flipped270 => "flipped-270",
+        }
+    }
+
+    /// whether width and height are swapped on screen
+    pub fn is_rotated(self) -> bool {
+        matches!(
+            self,
+            Transform::Rotate90
+                | Transform::Rotate270
+                | Transform::Flipped90
+                | Transform::Flipped270
+        )
+    }
+}
+
+impl FromStr for Transform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Transform::ALL
+            .into_iter()
+            .find(|t| t.as_str() == s)
+            .ok_or_else(|| {
+                let valid: Vec<_> = Transform::ALL.iter().map(|t| t.as_str()).collect();
+                anyhow::anyhow!(
+                    "Invalid transform '{s}', expected one of {}",
+                    valid.join(", ")
+                )
+            })
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// mode as written in the config, `WIDTHxHEIGHT[@RATE[Hz]]`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModeSpec {
+    pub width: i32,
+    pub height: i32,
+    pub refresh: Option<f64>,
+}
+
+/// how far an advertised rate may be off a fractional one from the config, in millihertz
+const REFRESH_TOLERANCE: i32 = 50;
+
+impl ModeSpec {
+    /// A rate written as a whole number matches every rate rounding to it, so `@60`
+    /// matches 59.95Hz, a fractional one every rate within 0.05Hz, so `@59.95` matches
+    /// 59.951Hz.
+    pub fn matches(&self, mode: &Mode) -> bool {
+        mode.width == self.width
+            && mode.height == self.height
+            && self.refresh.is_none_or(|refresh| {
+                if refresh.fract() == 0.0 {
+                    mode.refresh.round() == refresh
+                } else {
+                    (to_millihertz(refresh) - to_millihertz(mode.refresh)).abs()
+                        <= REFRESH_TOLERANCE
+                }
+            })
+    }
+
+    /// the matching mode of `modes` closest to the requested rate
+    pub fn find<'a>(&self, modes: &'a [Mode]) -> Option<&'a Mode> {
+        let distance = |mode: &Mode| {
+            self.refresh.map_or(0, |refresh| {
+                (to_millihertz(refresh) - to_millihertz(mode.refresh)).abs()
+            })
+        };
+        modes
+            .iter()
+            .filter(|m| self.matches(m))
+            .min_by_key(|m| distance(m))
+    }
+}
+
+/// one entry of a setting's `mode`, either a mode or a keyword picking one of the
+/// modes the output advertises
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModeCandidate {
+    Exact(ModeSpec),
+    Preferred,
+    /// the largest mode, at its highest refresh rate
+    MaxResolution,
+    /// the mode with the highest refresh rate, at its largest size
+    MaxRefresh,
+}
+
+impl ModeCandidate {
+    pub fn select<'a>(&self, output: &'a OutputInfo) -> Option<&'a Mode> {
+        let area = |m: &Mode| i64::from(m.width) * i64::from(m.height);
+        match self {
+            ModeCandidate::Exact(spec) => spec.find(&output.modes),
+            ModeCandidate::Preferred => output.preferred_mode(),
+            ModeCandidate::MaxResolution => output
+                .modes
+                .iter()
+                .max_by_key(|m| (area(m), to_millihertz(m.refresh))),
+            ModeCandidate::MaxRefresh => output
+                .modes
+                .iter()
+                .max_by_key(|m| (to_millihertz(m.refresh), area(m))),
+        }
+    }
+}
+
+impl FromStr for ModeCandidate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "preferred" => Ok(ModeCandidate::Preferred),
+            "max-resolution" => Ok(ModeCandidate::MaxResolution),
+            "max-refresh" => Ok(ModeCandidate::MaxRefresh),
+            _ => s.parse().map(ModeCandidate::Exact).map_err(|_| {
+                anyhow::anyhow!(
+                    "Invalid mode '{s}', expected WIDTHxHEIGHT[@RATE[Hz]], preferred, \
+                     max-resolution or max-refresh"
+                )
+            }),
+        }
+    }
+}
+
+impl FromStr for ModeSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid mode '{s}', expected WIDTHxHEIGHT[@RATE[Hz]]");
+
+        let (size, refresh) = match s.split_once('@') {
+            Some((size, refresh)) => (size, Some(refresh)),
+            None => (s, None),
+        };
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        let width: i32 = width.parse().map_err(|_| invalid())?;
+        let height: i32 = height.parse().map_err(|_| invalid())?;
+        if width <= 0 || height <= 0 {
+            return Err(invalid());
+        }
+
+        let refresh = match refresh {
+            Some(refresh) => {
+                let refresh: f64 = refresh
+                    .strip_suffix("Hz")
+                    .unwrap_or(refresh)
+                    .parse()
+                    .map_err(|_| invalid())?;
+                if !refresh.is_finite() || refresh <= 0.0 {
+                    return Err(invalid());
+                }
+                Some(refresh)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            width,
+            height,
+            refresh,
+        })
+    }
+}
+
+pub fn to_millihertz(refresh: f64) -> i32 {
+    (refresh * 1000.0).round() as i32
+}
+
+/// whether `name` looks like a connector such as `DP-3` or `HDMI-A-1`, not a pattern
+pub fn is_connector_name(name: &str) -> bool {
+    name.rsplit_once('-').is_some_and(|(kind, number)| {
+        !kind.is_empty()
+            && !number.is_empty()
+            && number.chars().all(|c| c.is_ascii_digit())
+            && kind.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    })
+}
+
+/// what an output pattern matched, ordered from the least to the most specific
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchKind {
+    /// any glob pattern, no matter what it matched
+    Wildcard,
+    Name,
+    MakeModel,
+    /// make, model and serial
+    Identifier,
+    Serial,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+/// physical size of the panel in millimetres, 0 when the output doesn't report it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhysicalSize {
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutputInfo {
     pub name: String,
     pub make: Option<String>,
     pub model: Option<String>,
     pub serial: Option<String>,
+    #[serde(default)]
+    pub physical_size: PhysicalSize,
+
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub modes: Vec<Mode>,
+    #[serde(default)]
+    pub position: Position,
+    #[serde(default)]
+    pub transform: Transform,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub adaptive_sync: bool,
+}
+
+impl Default for OutputInfo {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            make: None,
+            model: None,
+            serial: None,
+            physical_size: PhysicalSize::default(),
+            enabled: false,
+            modes: Vec::new(),
+            position: Position::default(),
+            transform: Transform::default(),
+            scale: default_scale(),
+            adaptive_sync: false,
+        }
+    }
 }
 
 impl OutputInfo {
@@ -24,15 +349,99 @@ impl OutputInfo {
     }
 
     pub fn matches_pattern(&self, pattern: &Pattern) -> bool {
-        pattern.matches(&self.name)
-            || self
-                .build_identifier()
-                .as_deref()
-                .is_some_and(|id| pattern.matches(id))
-            || self
-                .serial
-                .as_deref()
-                .is_some_and(|serial| pattern.matches(serial))
+        self.match_kind(pattern).is_some()
+    }
+
+    /// what `pattern` matched on this output, the most specific target wins
+    pub fn match_kind(&self, pattern: &Pattern) -> Option<MatchKind> {
+        let kind = if self
+            .serial
+            .as_deref()
+            .is_some_and(|serial| pattern.matches(serial))
+        {
+            MatchKind::Serial
+        } else if let Some(identifier) = self.build_identifier()
+            && pattern.matches(&identifier)
+        {
+            if self.serial.as_deref().is_some_and(|s| !s.is_empty()) {
+                MatchKind::Identifier
+            } else {
+                MatchKind::MakeModel
+            }
+        } else if pattern.matches(&self.name) {
+            MatchKind::Name
+        } else {
+            return None;
+        };
+
+        let is_glob = pattern.as_str().contains(['*', '?', '[']);
+        Some(if is_glob { MatchKind::Wildcard } else { kind })
+    }
+
+    pub fn current_mode(&self) -> Option<&Mode> {
+        self.modes.iter().find(|m| m.current)
+    }
+
+    pub fn preferred_mode(&self) -> Option<&Mode> {
+        self.modes
+            .iter()
+            .find(|m| m.preferred)
+            .or_else(|| self.modes.first())
+    }
+
+    /// marks `mode` as current, adding it as a custom mode if the output doesn't advertise it
+    pub fn set_current_mode(&mut self, mode: &Mode) {
+        let mut found = false;
+        for m in &mut self.modes {
+            m.current = !found
+                && m.width == mode.width
+                && m.height == mode.height
+                && to_millihertz(m.refresh) == to_millihertz(mode.refresh);
+            found |= m.current;
+        }
+
+        if !found {
+            self.modes.push(Mode {
+                current: true,
+                preferred: false,
+                ..mode.clone()
+            });
+        }
+    }
+
+    /// whether the output is a built-in panel, usually looked at from closer than a monitor
+    pub fn is_internal(&self) -> bool {
+        ["eDP-", "LVDS-", "DSI-"]
+            .iter()
+            .any(|prefix| self.name.starts_with(prefix))
+    }
+
+    /// pixels per inch of the current mode, `None` if the physical size is unknown
+    pub fn dpi(&self) -> Option<f64> {
+        let mode = self.current_mode()?;
+        if self.physical_size.width <= 0 || self.physical_size.height <= 0 {
+            return None;
+        }
+        Some(f64::from(mode.width) * 25.4 / f64::from(self.physical_size.width))
+    }
+
+    /// size of the output in the global compositor space
+    pub fn logical_size(&self) -> (i32, i32) {
+        let Some(mode) = self.current_mode() else {
+            return (0, 0);
+        };
+
+        let (width, height) = if self.transform.is_rotated() {
+            (mode.height, mode.width)
+        } else {
+            (mode.width, mode.height)
+        };
+
+        let scale = if self.scale > 0.0 { self.scale } else { 1.0 };
+        (
+            (f64::from(width) / scale) as i32,
+            (f64::from(height) / scale) as i32,
+        )
     }
 }
 
@@ -45,23 +454,6 @@ impl fmt::Display for OutputInfo {
     }
 }
 
-pub fn get_outputs() -> Result<Vec<OutputInfo>> {
-    let output = Command::new("wlr-randr")
-        .arg("--json")
-        .output()
-        .context("Failed to run wlr-randr")?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("wlr-randr failed: {stderr}");
-    }
-
-    let outputs: Vec<OutputInfo> =
-        serde_json::from_slice(&output.stdout).context("Failed to parse wlr-randr output")?;
-
-    Ok(outputs)
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,6 +469,17 @@ mod tests {
             make: make.map(String::from),
             model: model.map(String::from),
             serial: serial.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    fn make_mode(width: i32, height: i32, refresh: f64) -> Mode {
+        Mode {
+            width,
+            height,
+            refresh,
+            preferred: false,
+            current: false,
         }
     }
 
@@ -152,6 +555,35 @@ mod tests {
         assert!(output.matches_pattern(&Pattern::new("ABC123456").unwrap()));
     }
 
+    #[test]
+    fn test_match_kind() {
+        let output = make_output(
+            "HDMI-1",
+            Some("Dell Inc."),
+            Some("U2718Q"),
+            Some("ABC123456"),
+        );
+        let kind = |p: &str| output.match_kind(&Pattern::new(p).unwrap());
+
+        assert_eq!(kind("ABC123456"), Some(MatchKind::Serial));
+        assert_eq!(
+            kind("Dell Inc. U2718Q ABC123456"),
+            Some(MatchKind::Identifier)
+        );
+        assert_eq!(kind("HDMI-1"), Some(MatchKind::Name));
+        assert_eq!(kind("Dell Inc. * ABC123456"), Some(MatchKind::Wildcard));
+        assert_eq!(kind("HDMI-*"), Some(MatchKind::Wildcard));
+        assert_eq!(kind("Dell Inc. U2718Q"), None);
+
+        let output = make_output("DP-1", Some("Dell Inc."), Some("U2718Q"), None);
+        assert_eq!(
+            output.match_kind(&Pattern::new("Dell Inc. U2718Q").unwrap()),
+            Some(MatchKind::MakeModel)
+        );
+        assert!(MatchKind::Serial > MatchKind::Identifier);
+        assert!(MatchKind::Name > MatchKind::Wildcard);
+    }
+
     #[test]
     fn test_matches_pattern_no_match() {
         let output = make_output(
@@ -162,4 +594,155 @@ mod tests {
         );
         assert!(!output.matches_pattern(&Pattern::new("NonExistent").unwrap()));
     }
+
+    #[test]
+    fn test_mode_spec_parse() {
+        assert_eq!(
+            "1920x1080".parse::<ModeSpec>().unwrap(),
+            ModeSpec {
+                width: 1920,
+                height: 1080,
+                refresh: None
+            }
+        );
+        assert_eq!(
+            "2560x1440@143.912Hz".parse::<ModeSpec>().unwrap().refresh,
+            Some(143.912)
+        );
+        assert_eq!(
+            "2560x1440@60".parse::<ModeSpec>().unwrap().refresh,
+            Some(60.0)
+        );
+        assert!("1920".parse::<ModeSpec>().is_err());
+        assert!("1920x".parse::<ModeSpec>().is_err());
+        assert!("1920x1080@".parse::<ModeSpec>().is_err());
+        assert!("1920x1080@fastHz".parse::<ModeSpec>().is_err());
+        assert!("-1920x1080".parse::<ModeSpec>().is_err());
+    }
+
+    #[test]
+    fn test_mode_spec_matches() {
+        let spec: ModeSpec = "1920x1080@59.951Hz".parse().unwrap();
+        assert!(spec.matches(&make_mode(1920, 1080, 59.951)));
+        assert!(!spec.matches(&make_mode(1920, 1080, 60.002)));
+        assert!(!spec.matches(&make_mode(1280, 720, 59.951)));
+
+        let spec: ModeSpec = "1920x1080@59.95".parse().unwrap();
+        assert!(spec.matches(&make_mode(1920, 1080, 59.951)));
+        assert!(spec.matches(&make_mode(1920, 1080, 59.94)));
+        assert!(!spec.matches(&make_mode(1920, 1080, 59.89)));
+        let modes = [make_mode(1920, 1080, 59.94), make_mode(1920, 1080, 59.951)];
+        assert_eq!(spec.find(&modes), Some(&modes[1]));
+
+        let spec: ModeSpec = "1920x1080".parse().unwrap();
+        assert!(spec.matches(&make_mode(1920, 1080, 60.0)));
+
+        let spec: ModeSpec = "1920x1080@60".parse().unwrap();
+        assert!(spec.matches(&make_mode(1920, 1080, 59.951)));
+        assert!(!spec.matches(&make_mode(1920, 1080, 59.4)));
+
+        let modes = [
+            make_mode(1920, 1080, 59.951),
+            make_mode(1920, 1080, 60.0),
+            make_mode(1920, 1080, 60.2),
+        ];
+        assert_eq!(spec.find(&modes), Some(&modes[1]));
+    }
+
+    #[test]
+    fn test_mode_candidate_select() {
+        let output = OutputInfo {
+            modes: vec![
+                make_mode(1920, 1080, 144.0),
+                Mode {
+                    preferred: true,
+                    ..make_mode(2560, 1440, 60.0)
+                },
+                make_mode(2560, 1440, 59.951),
+                make_mode(1280, 720, 165.0),
+            ],
+            ..Default::default()
+        };
+        let select = |s: &str| s.parse::<ModeCandidate>().unwrap().select(&output).cloned();
+
+        assert_eq!(select("preferred"), Some(output.modes[1].clone()));
+        assert_eq!(select("max-resolution"), Some(output.modes[1].clone()));
+        assert_eq!(select("max-refresh"), Some(output.modes[3].clone()));
+        assert_eq!(select("1920x1080@144"), Some(output.modes[0].clone()));
+        assert_eq!(select("3840x2160"), None);
+        assert!("biggest".parse::<ModeCandidate>().is_err());
+    }
+
+    #[test]
+    fn test_transform_roundtrip() {
+        for transform in Transform::ALL {
+            assert_eq!(transform.as_str().parse::<Transform>().unwrap(), transform);
+        }
+        assert!("sideways".parse::<Transform>().is_err());
+    }
+
+    #[test]
+    fn test_set_current_mode() {
+        let mut output = make_output("HDMI-1", None, None, None);
+        output.modes = vec![make_mode(1920, 1080, 60.0), make_mode(1280, 720, 60.0)];
+
+        output.set_current_mode(&make_mode(1280, 720, 60.0));
+        assert_eq!(output.current_mode(), Some(&output.modes[1]));
+
+        output.set_current_mode(&make_mode(1024, 768, 75.0));
+        assert_eq!(output.modes.len(), 3);
+        assert_eq!(output.current_mode().unwrap().width, 1024);
+        assert_eq!(output.modes.iter().filter(|m| m.current).count(), 1);
+    }
+
+    #[test]
+    fn test_logical_size() {
+        let mut output = make_output("eDP-1", None, None, None);
+        assert_eq!(output.logical_size(), (0, 0));
+
+        output.set_current_mode(&make_mode(2880, 1800, 60.0));
+        output.scale = 2.0;
+        assert_eq!(output.logical_size(), (1440, 900));
+
+        output.transform = Transform::Rotate90;
+        assert_eq!(output.logical_size(), (900, 1440));
+    }
+
+    #[test]
+    fn test_deserialize_wlr_randr_json() {
+        let json = r#"[{
+            "name": "eDP-1",
+            "description": "Built-in display",
+            "make": "BOE",
+            "model": "0x0BCA",
+            "serial": "",
+            "physical_size": {"width": 300, "height": 190},
+            "enabled": true,
+            "modes": [
+                {"width": 2880, "height": 1800, "refresh": 90.0, "preferred": true, "current": true},
+                {"width": 2880, "height": 1800, "refresh": 60.0, "preferred": false, "current": false}
+            ],
+            "position": {"x": 0, "y": 0},
+            "transform": "normal",
+            "scale": 1.5,
+            "adaptive_sync": false
+        }]"#;
+
+        let outputs: Vec<OutputInfo> = serde_json::from_str(json).unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert!(outputs[0].enabled);
+        assert_eq!(outputs[0].modes.len(), 2);
+        assert_eq!(outputs[0].scale, 1.5);
+        assert_eq!(outputs[0].transform, Transform::Normal);
+        assert_eq!(outputs[0].logical_size(), (1920, 1200));
+        assert_eq!(
+            outputs[0].physical_size,
+            PhysicalSize {
+                width: 300,
+                height: 190
+            }
+        );
+        assert!(outputs[0].is_internal());
+        assert_eq!(outputs[0].dpi().unwrap().round(), 244.0);
+    }
 }
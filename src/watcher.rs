@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// how long the config has to stay untouched before it is reloaded, editors
+/// tend to write a file in several steps
+pub const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Watches the config file for changes through inotify.
+///
+/// The file itself is watched so edits through a symlink are seen, its directory so
+/// editors that save by renaming a new file over the old one are seen too.
+pub struct ConfigWatcher {
+    inotify: Inotify,
+    path: PathBuf,
+    file_name: OsString,
+    file_watch: Option<WatchDescriptor>,
+    deadline: Option<Instant>,
+}
+
+impl ConfigWatcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file_name = path
+            .file_name()
+            .with_context(|| format!("Config path {path:?} has no file name"))?
+            .to_os_string();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let inotify = Inotify::init().context("Failed to initialize inotify")?;
+        inotify
+            .watches()
+            .add(
+                &dir,
+                WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE,
+            )
+            .with_context(|| format!("Failed to watch config directory {dir:?}"))?;
+
+        let mut watcher = Self {
+            inotify,
+            path,
+            file_name,
+            file_watch: None,
+            deadline: None,
+        };
+        watcher.watch_file();
+        Ok(watcher)
+    }
+
+    /// (re)attach the watch on the file, the old one goes away with the inode when
+    /// the file gets replaced
+    fn watch_file(&mut self) {
+        match self
+            .inotify
+            .watches()
+            .add(&self.path, WatchMask::CLOSE_WRITE | WatchMask::MODIFY)
+        {
+            Ok(wd) => self.file_watch = Some(wd),
+            Err(e) => {
+                log::debug!("Failed to watch config file {:?}: {e}", self.path);
+                self.file_watch = None;
+            }
+        }
+    }
+
+    /// Drain pending inotify events and push the reload deadline back if any of them
+    /// concern the config file.
+    pub fn handle_events(&mut self) -> Result<()> {
+        let mut buffer = [0; 4096];
+        loop {
+            let events = match self.inotify.read_events(&mut buffer) {
+                Ok(events) => events,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e).context("Failed to read inotify events"),
+            };
+
+            let mut changed = false;
+            for event in events {
+                let on_file = self.file_watch.as_ref() == Some(&event.wd);
+                let on_name = event.name == Some(self.file_name.as_os_str());
+                if on_file || on_name {
+                    log::debug!("Config file event: {:?}", event.mask);
+                    changed = true;
+                }
+            }
+
+            if changed {
+                self.deadline = Some(Instant::now() + DEBOUNCE);
+            }
+        }
+    }
+
+    /// time left until the config should be reloaded, `None` when nothing changed
+    pub fn timeout(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Whether the debounce period has passed, resets the pending change if so.
+    pub fn take_due(&mut self) -> bool {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => {
+                self.deadline = None;
+                self.watch_file();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl AsRawFd for ConfigWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}
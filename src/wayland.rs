@@ -1,34 +1,100 @@
-use crate::config::{Config, Profile};
-use crate::output::{OutputInfo, get_outputs};
-use anyhow::Result;
+use crate::backend::{self, BackendKind, NativeBackend, OutputBackend, WlrRandrBackend};
+use crate::children::{self, ChildTracker};
+use crate::config::{Config, FALLBACK_PROFILE_ID, Fallback, Hook, Profile};
+use crate::history::{self, ProfileHistory};
+use crate::hooks::HookContext;
+use crate::ipc::SwitchOptions;
+use crate::layout;
+use crate::output::OutputInfo;
+use anyhow::{Context, Result};
+use serde::Serialize;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use wayland_client::{
     Connection, Dispatch, EventQueue, QueueHandle,
     globals::{GlobalListContents, registry_queue_init},
     protocol::{wl_output, wl_registry},
 };
 
+/// outcome of the last attempt to activate a profile
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApplyResult {
+    pub profile: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// profile chosen by hand, kept active instead of the one matching the outputs
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Pin {
+    pub profile: String,
+    /// connected outputs when the profile was chosen, sorted
+    pub outputs: Vec<String>,
+    /// whether the pin survives a change of the connected outputs
+    pub sticky: bool,
+}
+
+/// manual switch reverted unless it gets confirmed before `deadline`
+struct PendingConfirmation {
+    profile: String,
+    previous_profile: Option<String>,
+    previous_pin: Option<Pin>,
+    /// outputs before the switch, restored when there was no profile to go back to
+    snapshot: Vec<OutputInfo>,
+    /// names of the outputs connected at the switch
+    outputs: Vec<String>,
+    deadline: Instant,
+}
+
 pub struct WaylandState {
     pub config: Config,
     pub outputs: Vec<OutputInfo>,
     pub active_profile_id: Option<String>,
     pub name_map: HashMap<String, String>,
+    pub last_apply: Option<ApplyResult>,
+    pub children: ChildTracker,
+    pub pin: Option<Pin>,
+    /// automatic matching is suspended, profiles only change when switched to by hand
+    pub locked: bool,
+    /// profile last chosen by hand for each set of outputs, preferred when it matches
+    pub history: ProfileHistory,
+    /// outputs connected when the active profile was applied
+    applied_outputs: Vec<String>,
+    /// `on_deactivate` hooks of the active profile and what they get to know about it
+    on_deactivate: Vec<Hook>,
+    active_context: HookContext,
+    pending_confirmation: Option<PendingConfirmation>,
+    /// profile that last failed to activate and the outputs connected at the time, not
+    /// retried automatically until either them or the config change
+    failed: Option<(String, Vec<String>)>,
+    backend: Box<dyn OutputBackend>,
     pending_update: bool,
 }
 
 impl WaylandState {
-    pub fn new(config: Config) -> Self {
+    pub fn new(config: Config, backend: Box<dyn OutputBackend>) -> Self {
         Self {
             config,
             outputs: Vec::new(),
             active_profile_id: None,
             name_map: HashMap::new(),
+            last_apply: None,
+            children: ChildTracker::default(),
+            pin: None,
+            locked: false,
+            history: ProfileHistory::default(),
+            applied_outputs: Vec::new(),
+            on_deactivate: Vec::new(),
+            active_context: HookContext::default(),
+            pending_confirmation: None,
+            failed: None,
+            backend,
             pending_update: false,
         }
     }
 
     pub fn refresh_outputs(&mut self) {
-        match get_outputs() {
+        match self.backend.outputs() {
             Ok(outputs) => {
                 log::debug!(
                     "Got {} outputs: {:?}",
@@ -44,7 +110,7 @@ impl WaylandState {
         }
     }
 
-    fn run_commands(commands: &[String]) {
+    fn run_commands(&mut self, context: &HookContext, commands: &[String]) {
         for command in commands {
             if command.is_empty() {
                 log::warn!("Encountered an empty command, skipping.");
@@ -52,42 +118,315 @@ impl WaylandState {
             }
 
             log::debug!("Executing command: {command}");
-            if let Err(e) = std::process::Command::new("sh")
-                .arg("-c")
-                .arg(command)
-                .spawn()
-            {
+            if let Err(e) = self.children.spawn(context.command(command), command) {
                 log::error!("Failed to execute command '{command}': {e}");
             }
         }
     }
 
-    fn activate_profile(&mut self, profile_id: &str, profile: &Profile, reload: bool) {
-        if self.active_profile_id.as_deref() == Some(profile_id) && !reload {
-            log::debug!("Profile '{profile_id}' is already active, skipping.");
+    /// runs `hooks` in order, waiting for each, a failing hook doesn't stop the rest
+    fn run_hooks(context: &HookContext, hooks: &[Hook]) {
+        for hook in hooks {
+            let command = context.command(&hook.command);
+            if let Err(e) = children::run_and_wait(command, &hook.command, hook.timeout) {
+                log::error!("{e:#}");
+            }
+        }
+    }
+
+    fn activate_profile(
+        &mut self,
+        profile_id: &str,
+        profile: &Profile,
+        reload: bool,
+    ) -> Result<()> {
+        let result = self.try_activate_profile(profile_id, profile, reload);
+        self.failed = result
+            .is_err()
+            .then(|| (profile_id.to_string(), self.connected_names()));
+        result
+    }
+
+    /// Activates `profile` when it's matched automatically, unless it already failed on
+    /// the connected outputs. Retrying it would fail the same way, and the rollback of
+    /// every attempt is reported as an output change again.
+    fn activate_matched(&mut self, profile_id: &str, profile: &Profile, reload: bool) {
+        if !reload
+            && let Some((failed_id, outputs)) = &self.failed
+            && failed_id == profile_id
+            && *outputs == self.connected_names()
+        {
+            log::debug!("Profile '{profile_id}' failed on these outputs before, not retrying");
             return;
         }
+        if let Err(e) = self.activate_profile(profile_id, profile, reload) {
+            log::error!("{e:#}");
+        }
+    }
+
+    fn try_activate_profile(
+        &mut self,
+        profile_id: &str,
+        profile: &Profile,
+        reload: bool,
+    ) -> Result<()> {
+        let connected: Vec<String> = self.outputs.iter().map(|o| o.name.clone()).collect();
+        if self.active_profile_id.as_deref() == Some(profile_id)
+            && self.applied_outputs == connected
+            && !reload
+        {
+            log::debug!("Profile '{profile_id}' is already active, skipping.");
+            return Ok(());
+        }
 
         log::info!("Activating profile: '{profile_id}'");
-        let commands = profile.generate_commands(&self.name_map);
-        Self::run_commands(&commands);
+        let previous_profile = self.active_profile_id.clone();
+        let prepared = self
+            .prepare_profile(profile, &self.name_map)
+            .and_then(|profile| {
+                self.test_settings(profile_id, &profile)?;
+                Ok(profile)
+            });
+        let profile = match prepared {
+            Ok(profile) => profile,
+            Err(e) => {
+                let result = Err(e);
+                self.record_apply::<()>(profile_id, &result);
+                return result;
+            }
+        };
+        let profile = &profile;
+        let mut context = HookContext {
+            profile: profile_id.to_string(),
+            previous_profile,
+            name_map: self.name_map.clone(),
+            outputs: self.outputs.clone(),
+        };
+        Self::run_hooks(&context, &profile.pre_exec);
+        let result = self.apply_settings(profile_id, profile);
+        self.record_apply(profile_id, &result);
+        // let the hooks see the outputs as the profile left them
+        context.outputs = result?;
+
+        // only left once the outputs are really in the new profile's layout
+        if context.previous_profile.as_deref() != Some(profile_id) {
+            self.deactivate_profile();
+        }
+        Self::run_hooks(&context, &profile.post_exec);
+        self.run_commands(&context, &profile.exec);
         self.active_profile_id = Some(profile_id.to_string());
+        self.applied_outputs = connected;
+        self.on_deactivate = profile.on_deactivate.clone();
+        self.active_context = context;
+        Ok(())
+    }
+
+    fn record_apply<T>(&mut self, profile_id: &str, result: &Result<T>) {
+        self.last_apply = Some(ApplyResult {
+            profile: profile_id.to_string(),
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        });
+    }
+
+    /// Runs the `on_deactivate` hooks of the active profile and forgets about it.
+    fn deactivate_profile(&mut self) {
+        let Some(profile_id) = self.active_profile_id.take() else {
+            return;
+        };
+        self.applied_outputs.clear();
+        self.pending_confirmation = None;
+
+        let hooks = std::mem::take(&mut self.on_deactivate);
+        let mut context = std::mem::take(&mut self.active_context);
+        if hooks.is_empty() {
+            return;
+        }
+
+        log::info!("Deactivating profile: '{profile_id}'");
+        context.outputs = self.outputs.clone();
+        Self::run_hooks(&context, &hooks);
+    }
+
+    /// the profile as it gets applied to the connected outputs, with plain positions
+    fn prepare_profile(
+        &self,
+        profile: &Profile,
+        name_map: &HashMap<String, String>,
+    ) -> Result<Profile> {
+        let profile = profile.resolve_relative_targets(name_map)?;
+        let profile = backend::resolve_settings(&profile, &self.outputs, name_map)?;
+        let profile = layout::resolve_positions(&profile, &self.outputs, name_map)?;
+        // placed next to the outputs of the profile where they ended up
+        let profile = profile.with_extra_outputs(&self.outputs, name_map)?;
+        layout::resolve_positions(&profile, &self.outputs, name_map)
+    }
+
+    /// asks the compositor whether it would accept `profile`, with `test_before_apply`
+    fn test_settings(&mut self, profile_id: &str, profile: &Profile) -> Result<()> {
+        if profile.settings.is_empty() || !self.config.test_before_apply {
+            return Ok(());
+        }
+        self.backend
+            .test(profile, &self.name_map)
+            .with_context(|| format!("Compositor rejected profile '{profile_id}'"))
+    }
+
+    /// Applies the output settings of `profile` and returns the outputs as they ended up.
+    ///
+    /// When applying fails or the outputs don't match the profile afterwards, the
+    /// outputs are restored to the state they were in before.
+    fn apply_settings(&mut self, profile_id: &str, profile: &Profile) -> Result<Vec<OutputInfo>> {
+        if profile.settings.is_empty() {
+            return Ok(self.outputs.clone());
+        }
+
+        let snapshot = self
+            .backend
+            .outputs()
+            .context("Failed to get outputs before applying")?;
+        let expected = backend::plan_configuration(&snapshot, &profile.settings, &self.name_map)?;
+
+        let result = self
+            .backend
+            .apply(profile, &self.name_map)
+            .with_context(|| format!("Failed to apply output settings of '{profile_id}'"))
+            .and_then(|()| {
+                let actual = self
+                    .backend
+                    .outputs()
+                    .context("Failed to get outputs after applying")?;
+                backend::verify(&expected, &actual)
+                    .with_context(|| format!("Profile '{profile_id}' was not applied fully"))?;
+                Ok(actual)
+            });
+
+        if result.is_err() {
+            self.roll_back(&snapshot);
+        }
+        result
+    }
+
+    /// Brings the outputs back to `snapshot`, logging how it went.
+    fn roll_back(&mut self, snapshot: &[OutputInfo]) {
+        let restored = self.backend.outputs().and_then(|current| {
+            let profile = backend::restore_profile(snapshot, &current);
+            if profile.settings.is_empty() {
+                return Ok(false);
+            }
+            self.backend.apply(&profile, &HashMap::new()).map(|()| true)
+        });
+
+        match restored {
+            Ok(true) => log::warn!("Restored the previous output configuration"),
+            Ok(false) => log::debug!("Outputs are unchanged, nothing to restore"),
+            Err(e) => log::error!("Failed to restore the previous output configuration: {e:#}"),
+        }
+    }
+
+    fn connected_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.outputs.iter().map(|o| o.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    /// Schedules a refresh when the backend saw the outputs change, which covers heads that
+    /// don't come with a `wl_output`, like connected but disabled ones, and changes made by
+    /// other clients. Returns whether a refresh is due, and takes it.
+    pub fn check_backend_changes(&mut self) -> bool {
+        match self.backend.has_changes() {
+            Ok(true) => {
+                log::debug!("Output configuration changed, scheduling refresh");
+                self.pending_update = true;
+            }
+            Ok(false) => {}
+            Err(e) => log::error!("Failed to check for output changes: {e:#}"),
+        }
+        std::mem::take(&mut self.pending_update)
+    }
+
+    /// Keeps the pinned profile applied, `false` if there is no pin (anymore) and the
+    /// profile has to be matched.
+    fn apply_pinned(&mut self, reload: bool) -> bool {
+        let Some(pin) = &self.pin else {
+            return false;
+        };
+        let profile_id = pin.profile.clone();
+        let outputs_changed = pin.outputs != self.connected_names();
+        if !pin.sticky && outputs_changed {
+            log::info!("Connected outputs changed, unpinning profile '{profile_id}'");
+            self.pin = None;
+            return false;
+        }
+
+        let Ok(profile) = self.get_profile(&profile_id) else {
+            log::warn!("Pinned profile '{profile_id}' no longer exists, unpinning it");
+            self.pin = None;
+            return false;
+        };
+        log::debug!("Profile '{profile_id}' is pinned, not matching profiles");
+        match profile.match_outputs(&self.outputs) {
+            Some(matched) => self.name_map = matched.name_map,
+            // kept by a sticky pin, the outputs it was applied to may be gone
+            None if outputs_changed => {
+                self.name_map.clear();
+                let result = Err(anyhow::anyhow!(
+                    "Pinned profile '{profile_id}' doesn't match the connected outputs, not applying it"
+                ));
+                if let Err(e) = &result {
+                    log::error!("{e:#}");
+                }
+                self.record_apply::<()>(&profile_id, &result);
+                return true;
+            }
+            None => {}
+        }
+        self.activate_matched(&profile_id, &profile, reload);
+        true
     }
 
     pub fn evaluate_profiles(&mut self, reload: bool) {
-        let matched = self
-            .config
-            .find_matching_profile(&self.outputs)
+        if self.locked {
+            log::debug!("Automatic switching is locked, not matching profiles");
+            return;
+        }
+        if self.apply_pinned(reload) {
+            return;
+        }
+
+        let remembered = self.history.get(&self.outputs);
+        let mut matching = self.config.matching_profiles(&self.outputs);
+        if let Some(idx) = matching
+            .iter()
+            .position(|(id, _, _)| Some(*id) == remembered)
+        {
+            log::debug!(
+                "Preferring profile '{}' last chosen for these outputs",
+                matching[idx].0
+            );
+            matching.swap(0, idx);
+        }
+        let matched = matching
+            .into_iter()
+            .next()
             .map(|(id, profile, name_map)| (id.to_string(), profile.clone(), name_map));
 
         match matched {
             Some((profile_id, profile, name_map)) => {
                 self.name_map = name_map;
-                self.activate_profile(&profile_id, &profile, reload);
+                self.activate_matched(&profile_id, &profile, reload);
             }
             None => {
-                if self.active_profile_id.take().is_some() {
+                let fallback = self
+                    .config
+                    .fallback
+                    .as_ref()
+                    .filter(|_| !self.outputs.is_empty())
+                    .map(Fallback::to_profile);
+
+                if fallback.is_none() && self.active_profile_id.is_some() {
                     log::warn!("No matching profile found. Clearing active profile.");
+                    self.deactivate_profile();
                 }
 
                 if self.outputs.is_empty() {
@@ -101,21 +440,207 @@ impl WaylandState {
                         .join(", ");
                     log::warn!("No profile matches active outputs: [{outputs_str}]");
                 }
+
+                if let Some(fallback) = fallback {
+                    self.name_map.clear();
+                    self.activate_matched(FALLBACK_PROFILE_ID, &fallback, reload);
+                }
             }
         }
     }
 
-    pub fn apply_profile_by_name(&mut self, profile_id: &str) -> Result<String> {
-        let profile = self
-            .config
+    fn get_profile(&self, profile_id: &str) -> Result<Profile> {
+        if profile_id == FALLBACK_PROFILE_ID
+            && let Some(fallback) = &self.config.fallback
+        {
+            return Ok(fallback.to_profile());
+        }
+
+        self.config
             .profiles
             .get(profile_id)
-            .ok_or_else(|| anyhow::anyhow!("Profile '{profile_id}' not found."))?
-            .clone();
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Profile '{profile_id}' not found."))
+    }
 
-        self.activate_profile(profile_id, &profile, false);
+    pub fn apply_profile_by_name(&mut self, profile_id: &str) -> Result<String> {
+        let profile = self.get_profile(profile_id)?;
+        if let Some(matched) = profile.match_outputs(&self.outputs) {
+            self.name_map = matched.name_map;
+        }
+
+        self.activate_profile(profile_id, &profile, false)?;
         Ok(format!("Profile '{profile_id}' applied successfully."))
     }
+
+    /// Switches to `profile_id` by hand and pins it, so it stays active until the connected
+    /// outputs change, or for good with `sticky`. With `confirm`, goes back to the previous
+    /// profile unless [`Self::confirm`] is called within that time.
+    pub fn switch_profile(&mut self, profile_id: &str, options: &SwitchOptions) -> Result<String> {
+        let snapshot = match options.confirm {
+            Some(_) => self
+                .backend
+                .outputs()
+                .context("Failed to get outputs before switching")?,
+            None => Vec::new(),
+        };
+        let previous_profile = self.active_profile_id.clone();
+        let previous_pin = self.pin.clone();
+
+        self.apply_profile_by_name(profile_id)?;
+        self.pin = Some(Pin {
+            profile: profile_id.to_string(),
+            outputs: self.connected_names(),
+            sticky: options.sticky,
+        });
+
+        let Some(timeout) = options.confirm else {
+            self.remember(profile_id);
+            return Ok(format!("Profile switched successfully to {profile_id}"));
+        };
+        self.pending_confirmation = Some(PendingConfirmation {
+            profile: profile_id.to_string(),
+            previous_profile,
+            previous_pin,
+            snapshot,
+            outputs: self.connected_names(),
+            deadline: Instant::now() + timeout,
+        });
+        Ok(format!(
+            "Profile '{profile_id}' applied, run 'auto-wlr-randrctl confirm' within {}s to keep it.",
+            timeout.as_secs()
+        ))
+    }
+
+    pub fn confirm(&mut self) -> Result<String> {
+        let pending = self
+            .pending_confirmation
+            .take()
+            .context("No switch is waiting for confirmation.")?;
+        log::info!("Switch to profile '{}' confirmed", pending.profile);
+        self.remember(&pending.profile);
+        Ok(format!("Profile '{}' confirmed.", pending.profile))
+    }
+
+    /// Records `profile_id` as chosen for the connected outputs.
+    fn remember(&mut self, profile_id: &str) {
+        if let Err(e) = self.history.record(&self.outputs, profile_id) {
+            log::error!("Failed to remember profile '{profile_id}': {e:#}");
+        }
+    }
+
+    /// profile of the switch waiting for confirmation, if any
+    pub fn awaiting_confirmation(&self) -> Option<&str> {
+        self.pending_confirmation
+            .as_ref()
+            .map(|pending| pending.profile.as_str())
+    }
+
+    /// time left until an unconfirmed switch gets reverted
+    pub fn confirmation_timeout(&self) -> Option<Duration> {
+        self.pending_confirmation
+            .as_ref()
+            .map(|pending| pending.deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Reverts a switch that wasn't confirmed before its deadline.
+    pub fn revert_unconfirmed(&mut self) {
+        let Some(pending) = self
+            .pending_confirmation
+            .take_if(|pending| pending.deadline <= Instant::now())
+        else {
+            return;
+        };
+
+        log::warn!(
+            "Switch to profile '{}' was not confirmed in time, reverting",
+            pending.profile
+        );
+        self.pin = pending.previous_pin;
+        let previous = pending.previous_profile.filter(|previous| {
+            self.get_profile(previous)
+                .is_ok_and(|profile| profile.match_outputs(&self.outputs).is_some())
+        });
+        if let Some(previous) = previous {
+            if let Err(e) = self.apply_profile_by_name(&previous) {
+                log::error!("Failed to switch back to profile '{previous}': {e:#}");
+            }
+        } else if pending.outputs == self.connected_names() {
+            self.deactivate_profile();
+            self.roll_back(&pending.snapshot);
+        } else {
+            // outputs were plugged in or out since the switch, the old state doesn't fit them
+            self.evaluate_profiles(false);
+        }
+    }
+
+    /// ids of the profiles matching the connected outputs, best first
+    pub fn matching_profile_ids(&self) -> Vec<String> {
+        self.config
+            .matching_profiles(&self.outputs)
+            .into_iter()
+            .map(|(profile_id, _, _)| profile_id.to_string())
+            .collect()
+    }
+
+    /// Switches to the profile after the active one (or before it if not `forward`) among
+    /// those matching the connected outputs, wrapping around.
+    pub fn cycle_profile(&mut self, forward: bool) -> Result<String> {
+        let matching = self.matching_profile_ids();
+        if matching.is_empty() {
+            anyhow::bail!("No profile matches the connected outputs.");
+        }
+
+        let active = self
+            .active_profile_id
+            .as_ref()
+            .and_then(|id| matching.iter().position(|m| m == id));
+        let count = matching.len();
+        let next = match (active, forward) {
+            (Some(idx), true) => (idx + 1) % count,
+            (Some(idx), false) => (idx + count - 1) % count,
+            (None, true) => 0,
+            (None, false) => count - 1,
+        };
+
+        self.switch_profile(&matching[next], &SwitchOptions::default())
+    }
+
+    /// Suspends or resumes automatic matching, resuming matches the outputs right away.
+    pub fn set_locked(&mut self, locked: bool) -> String {
+        if self.locked == locked {
+            return format!(
+                "Automatic switching is already {}.",
+                if locked { "locked" } else { "unlocked" }
+            );
+        }
+
+        self.locked = locked;
+        if locked {
+            log::info!("Automatic switching locked");
+            "Automatic switching locked.".into()
+        } else {
+            log::info!("Automatic switching unlocked");
+            self.evaluate_profiles(false);
+            "Automatic switching unlocked.".into()
+        }
+    }
+
+    pub fn test_profile_by_name(&mut self, profile_id: &str) -> Result<String> {
+        let profile = self.get_profile(profile_id)?;
+        let name_map = profile
+            .match_outputs(&self.outputs)
+            .map(|matched| matched.name_map)
+            .unwrap_or_else(|| self.name_map.clone());
+        let profile = self.prepare_profile(&profile, &name_map)?;
+
+        self.backend
+            .test(&profile, &name_map)
+            .with_context(|| format!("Compositor would reject profile '{profile_id}'"))?;
+        Ok(format!(
+            "Profile '{profile_id}' would be accepted by the compositor."
+        ))
+    }
 }
 
 impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for WaylandState {
@@ -155,14 +680,20 @@ impl Dispatch<wl_output::WlOutput, ()> for WaylandState {
 
 pub fn init_wayland(
     config: Config,
+    backend_kind: BackendKind,
 ) -> Result<(Connection, WaylandState, EventQueue<WaylandState>)> {
     let conn = Connection::connect_to_env()?;
     let (_, event_queue) = registry_queue_init::<WaylandState>(&conn)?;
 
-    let mut state = WaylandState::new(config);
-
     log::info!("Initializing Wayland connection...");
 
+    let backend: Box<dyn OutputBackend> = match backend_kind {
+        BackendKind::Native => Box::new(NativeBackend::new(&conn)?),
+        BackendKind::WlrRandr => Box::new(WlrRandrBackend),
+    };
+    let mut state = WaylandState::new(config, backend);
+    state.history = ProfileHistory::load(history::default_history_path());
+
     state.refresh_outputs();
 
     Ok((conn, state, event_queue))
@@ -174,9 +705,11 @@ pub fn process_events(
 ) -> Result<()> {
     event_queue.roundtrip(state)?;
 
-    if state.pending_update {
-        state.pending_update = false;
+    while state.check_backend_changes() {
         state.refresh_outputs();
+        // the native backend shares our connection, so its roundtrips may have read
+        // events meant for this queue without the fd becoming readable again
+        event_queue.dispatch_pending(state)?;
     }
 
     Ok(())
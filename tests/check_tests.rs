@@ -0,0 +1,163 @@
+use assert_fs::TempDir;
+use assert_fs::prelude::*;
+use auto_wlr_randr::check::{Diagnostic, Severity, check_file, check_str};
+
+fn messages(diagnostics: &[Diagnostic]) -> Vec<String> {
+    diagnostics.iter().map(|d| d.to_string()).collect()
+}
+
+#[test]
+fn test_check_valid_config() {
+    let diagnostics = check_str(
+        r#"
+[profile.docked]
+layout = ["laptop", "DP-*"]
+
+[[profile.docked.settings]]
+output = "eDP-1"
+alias = "laptop"
+on = true
+mode = "1920x1080@60Hz"
+pos = "0,0"
+transform = "flipped-90"
+scale = 1.5
+
+[[profile.docked.settings]]
+output = "DP-*"
+on = true
+mode = ["2560x1440@60", "max-refresh"]
+left_of = "eDP-1"
+
+[[profile.docked.settings]]
+output = "HDMI-A-1"
+on = true
+above = "laptop"
+"#,
+    )
+    .unwrap();
+
+    assert!(diagnostics.is_empty(), "{:?}", messages(&diagnostics));
+}
+
+#[test]
+fn test_check_reports_invalid_values() {
+    let diagnostics = check_str(
+        r#"
+[profile.laptop]
+
+[[profile.laptop.settings]]
+output = "eDP-[1"
+mode = "1920x1080@fastHz"
+transform = "45"
+scale = 0.0
+pos = "0;0"
+"#,
+    )
+    .unwrap();
+
+    let messages = messages(&diagnostics);
+    assert_eq!(messages.len(), 5, "{messages:?}");
+    assert!(messages[0].starts_with("5:10: profile 'laptop': Invalid output pattern 'eDP-[1'"));
+    assert_eq!(
+        messages[1],
+        "6:8: profile 'laptop': Invalid mode '1920x1080@fastHz', expected WIDTHxHEIGHT[@RATE[Hz]], \
+         preferred, max-resolution or max-refresh"
+    );
+    assert!(messages[2].starts_with("7:13: profile 'laptop': Invalid transform '45'"));
+    assert_eq!(
+        messages[3],
+        "8:9: profile 'laptop': Invalid scale '0.0', expected a positive number or \"auto\""
+    );
+    assert_eq!(
+        messages[4],
+        "9:7: profile 'laptop': Invalid position '0;0', expected X,Y"
+    );
+}
+
+#[test]
+fn test_check_relative_position() {
+    let diagnostics = check_str(
+        r#"
+[profile.home]
+layout = ["eDP-1", "tv"]
+
+[[profile.home.settings]]
+output = "eDP-1"
+pos = "0,0"
+right_of = "DP-1"
+
+[[profile.home.settings]]
+output = "HDMI-A-1"
+left_of = "HDMI-A-1"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        messages(&diagnostics),
+        vec![
+            "8:12: profile 'home': 'right_of' can't be combined with 'pos' in the same setting",
+            "8:12: profile 'home': 'right_of' target 'DP-1' is not an output of this profile",
+            "12:11: profile 'home': 'left_of' target 'HDMI-A-1' is not an output of this profile",
+            "3:20: profile 'home': Layout entry 'tv' is not an output of this profile",
+        ]
+    );
+}
+
+#[test]
+fn test_check_targets_resolve_like_the_daemon() {
+    let diagnostics = check_str(
+        r#"
+[profile.desk]
+layout = ["HDMI-A-1", "Dell Inc. U2718Q ABC"]
+
+[[profile.desk.settings]]
+output = "Dell*"
+
+[[profile.desk.settings]]
+output = "eDP-1"
+left_of = "DP-3"
+
+[[profile.desk.settings]]
+output = "HDMI-A-1"
+right_of = "Dell Inc. U2718Q ABC"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        messages(&diagnostics),
+        vec![
+            "10:11: profile 'desk': warning: 'left_of' target 'DP-3' is not an alias or output \
+             of this profile, it only works if one of the outputs is connected as 'DP-3'",
+            "14:12: profile 'desk': 'right_of' target 'Dell Inc. U2718Q ABC' is not an output \
+             of this profile",
+            "3:23: profile 'desk': Layout entry 'Dell Inc. U2718Q ABC' is not an output of this \
+             profile",
+        ]
+    );
+    assert_eq!(diagnostics[0].severity, Severity::Warning);
+    assert_eq!(diagnostics[1].severity, Severity::Error);
+}
+
+#[test]
+fn test_check_file() {
+    let temp = TempDir::new().unwrap();
+    let config_file = temp.child("config.toml");
+
+    config_file
+        .write_str(
+            "[profile.laptop]\n\n[[profile.laptop.settings]]\noutput = \"eDP-1\"\nscale = -1.0\n",
+        )
+        .unwrap();
+    let diagnostics = check_file(config_file.path()).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].profile, "laptop");
+    assert_eq!((diagnostics[0].line, diagnostics[0].column), (5, 9));
+
+    config_file
+        .write_str("[profile.laptop]\nunknown = 1\n")
+        .unwrap();
+    let err = check_file(config_file.path()).unwrap_err();
+    assert!(format!("{err:#}").contains("line 2"), "{err:#}");
+}
@@ -1,7 +1,10 @@
 use assert_fs::TempDir;
 use assert_fs::prelude::*;
-use auto_wlr_randr::config::{Config, OutputSetting, Profile};
+use auto_wlr_randr::config::{
+    AutoScale, Config, DEFAULT_HOOK_TIMEOUT, Hook, ModeList, OutputSetting, Profile, Scale,
+};
 use auto_wlr_randr::output::OutputInfo;
+use indexmap::IndexMap;
 use rstest::*;
 use std::collections::HashMap;
 
@@ -72,6 +75,7 @@ fn make_output(
         make: make.map(String::from),
         model: model.map(String::from),
         serial: serial.map(String::from),
+        ..Default::default()
     }
 }
 
@@ -99,13 +103,14 @@ fn test_find_matching_profile(
     #[case] expected_profile_name: &str,
     #[case] should_match: bool,
 ) {
-    let mut profiles = HashMap::new();
+    let mut profiles = IndexMap::new();
 
     let laptop_profile = Profile {
         exec: vec![],
         settings: vec![OutputSetting {
             output: "eDP-1".into(),
-            on: true,
+            alias: None,
+            on: Some(true),
             mode: Some("1920x1080".into()),
             preferred: false,
             pos: Some("0,0".into()),
@@ -114,16 +119,18 @@ fn test_find_matching_profile(
             above: None,
             below: None,
             transform: None,
-            scale: Some(1.0),
-            adaptive_sync: false,
+            scale: Some(Scale::Fixed(1.0)),
+            adaptive_sync: None,
         }],
+        ..Default::default()
     };
     profiles.insert("laptop".to_string(), laptop_profile);
 
     let docked_settings = vec![
         OutputSetting {
             output: "eDP-1".into(),
-            on: false,
+            alias: None,
+            on: Some(false),
             mode: None,
             preferred: false,
             pos: None,
@@ -133,11 +140,12 @@ fn test_find_matching_profile(
             below: None,
             transform: None,
             scale: None,
-            adaptive_sync: false,
+            adaptive_sync: None,
         },
         OutputSetting {
             output: "HDMI-*".into(),
-            on: true,
+            alias: None,
+            on: Some(true),
             mode: Some("2560x1440".into()),
             preferred: false,
             pos: Some("0,0".into()),
@@ -146,14 +154,15 @@ fn test_find_matching_profile(
             above: None,
             below: None,
             transform: None,
-            scale: Some(1.0),
-            adaptive_sync: false,
+            scale: Some(Scale::Fixed(1.0)),
+            adaptive_sync: None,
         },
     ];
 
     let docked_profile = Profile {
         exec: vec![],
         settings: docked_settings,
+        ..Default::default()
     };
     profiles.insert("docked".to_string(), docked_profile);
 
@@ -184,12 +193,13 @@ fn test_find_matching_profile(
 }
 
 #[test]
-fn test_profile_generate_commands() {
+fn test_profile_generate_wlr_randr_args() {
     let profile = Profile {
         exec: vec!["echo 'Profile activated'".into()],
         settings: vec![OutputSetting {
             output: "HDMI-1".into(),
-            on: true,
+            alias: None,
+            on: Some(true),
             mode: Some("1920x1080".into()),
             preferred: false,
             pos: Some("0,0".into()),
@@ -198,25 +208,178 @@ fn test_profile_generate_commands() {
             above: None,
             below: None,
             transform: None,
-            scale: Some(1.0),
-            adaptive_sync: true,
+            scale: Some(Scale::Fixed(1.0)),
+            adaptive_sync: Some(true),
         }],
+        ..Default::default()
     };
 
     let mut name_map = HashMap::new();
     name_map.insert("HDMI-1".to_string(), "HDMI-A-1".to_string());
 
-    let commands = profile.generate_commands(&name_map);
+    let args = profile.generate_wlr_randr_args(&name_map).unwrap();
 
-    assert_eq!(commands.len(), 2);
-    assert!(commands[0].starts_with("wlr-randr"));
-    assert!(commands[0].contains("--output 'HDMI-A-1'"));
-    assert!(commands[0].contains("--on"));
-    assert!(commands[0].contains("--mode '1920x1080'"));
-    assert!(commands[0].contains("--pos '0,0'"));
-    assert!(commands[0].contains("--scale '1'"));
-    assert!(commands[0].contains("--adaptive-sync enabled"));
-    assert_eq!(commands[1], "echo 'Profile activated'");
+    assert_eq!(args[..3], ["--output", "HDMI-A-1", "--on"]);
+    assert!(args.windows(2).any(|w| w == ["--mode", "1920x1080"]));
+    assert!(args.windows(2).any(|w| w == ["--pos", "0,0"]));
+    assert!(args.windows(2).any(|w| w == ["--scale", "1"]));
+    assert!(args.windows(2).any(|w| w == ["--adaptive-sync", "enabled"]));
+}
+
+#[test]
+fn test_profile_generate_wlr_randr_args_quotes_are_not_special() {
+    let profile = Profile {
+        settings: vec![OutputSetting {
+            output: "Dell Inc. DELL U2720Q 'X'; rm -rf ~".into(),
+            on: Some(true),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        profile.generate_wlr_randr_args(&HashMap::new()),
+        Some(
+            ["--output", "Dell Inc. DELL U2720Q 'X'; rm -rf ~", "--on"]
+                .map(String::from)
+                .to_vec()
+        )
+    );
+}
+
+#[rstest]
+#[case("", &["--output", "DP-1", "--mode", "1920x1080"])]
+#[case(
+    "legacy_output_defaults = true",
+    &["--output", "DP-1", "--off", "--mode", "1920x1080", "--adaptive-sync", "disabled"]
+)]
+fn test_unset_on_leaves_output_alone(#[case] global: &str, #[case] expected: &[&str]) {
+    let config = load_config(&format!(
+        r#"
+{global}
+
+[profile.desk]
+
+[[profile.desk.settings]]
+output = "DP-1"
+mode = "1920x1080"
+"#
+    ));
+
+    let args = config.profiles["desk"].generate_wlr_randr_args(&HashMap::new());
+    assert_eq!(
+        args,
+        Some(expected.iter().map(|arg| arg.to_string()).collect())
+    );
+}
+
+#[test]
+fn test_scale_accepts_auto() {
+    let config = load_config(
+        r#"
+[profile.desk]
+
+[[profile.desk.settings]]
+output = "eDP-1"
+scale = "auto"
+
+[[profile.desk.settings]]
+output = "DP-1"
+scale = { dpi = 96, step = 0.125 }
+
+[[profile.desk.settings]]
+output = "DP-2"
+scale = 2
+"#,
+    );
+
+    let settings = &config.profiles["desk"].settings;
+    assert_eq!(settings[0].scale, Some(Scale::Auto(AutoScale::default())));
+    assert_eq!(
+        settings[1].scale,
+        Some(Scale::Auto(AutoScale {
+            dpi: Some(96.0),
+            step: Some(0.125)
+        }))
+    );
+    assert_eq!(settings[2].scale, Some(Scale::Fixed(2.0)));
+}
+
+#[rstest]
+#[case("\"big\"")]
+#[case("{ dpi = 0 }")]
+#[case("{ steps = 0.5 }")]
+fn test_scale_invalid(#[case] scale: &str) {
+    let temp = TempDir::new().unwrap();
+    let config_file = temp.child("config.toml");
+    config_file
+        .write_str(&format!(
+            "[profile.desk]\n\n[[profile.desk.settings]]\noutput = \"DP-1\"\nscale = {scale}\n"
+        ))
+        .unwrap();
+
+    assert!(Config::load_from_file(config_file.path()).is_err());
+}
+
+#[test]
+fn test_mode_accepts_list() {
+    let config = load_config(
+        r#"
+[profile.desk]
+
+[[profile.desk.settings]]
+output = "DP-1"
+mode = ["2560x1440@60", "max-refresh"]
+
+[[profile.desk.settings]]
+output = "eDP-1"
+mode = "preferred"
+"#,
+    );
+
+    let settings = &config.profiles["desk"].settings;
+    assert_eq!(
+        settings[0].mode,
+        Some(ModeList(vec!["2560x1440@60".into(), "max-refresh".into()]))
+    );
+    assert_eq!(settings[1].mode, Some("preferred".into()));
+}
+
+#[rstest]
+#[case("Dell Inc. U2718Q", Some("DP-3"))]
+#[case("DP-3", Some("DP-3"))]
+#[case("eDP-1", Some("eDP-1"))]
+#[case("HDMI-A-1", None)]
+#[case("Dell*", None)]
+fn test_resolve_relative_targets(#[case] target: &str, #[case] expected: Option<&str>) {
+    let config = load_config(&format!(
+        r#"
+[profile.desk]
+
+[[profile.desk.settings]]
+output = "eDP-1"
+left_of = "{target}"
+
+[[profile.desk.settings]]
+output = "Dell Inc. U2718Q"
+"#
+    ));
+    let name_map = HashMap::from([
+        ("eDP-1".to_string(), "eDP-1".to_string()),
+        ("Dell Inc. U2718Q".to_string(), "DP-3".to_string()),
+    ]);
+
+    let resolved = config.profiles["desk"].resolve_relative_targets(&name_map);
+
+    match expected {
+        Some(expected) => {
+            assert_eq!(
+                resolved.unwrap().settings[0].left_of.as_deref(),
+                Some(expected)
+            );
+        }
+        None => assert!(resolved.is_err()),
+    }
 }
 
 #[test]
@@ -238,3 +401,348 @@ fn test_reload_config() {
     assert_eq!(config.profiles.len(), 2);
     assert!(config.profiles.contains_key("docked"));
 }
+
+#[test]
+fn test_reload_config_keeps_old_on_error() {
+    let temp = TempDir::new().unwrap();
+    let config_file = temp.child("config.toml");
+
+    config_file.write_str("[profile.laptop]\n").unwrap();
+
+    let mut config = Config::load_from_file(config_file.path()).unwrap();
+
+    config_file.write_str("[profile.laptop\n").unwrap();
+
+    assert!(config.reload_config().is_err());
+    assert!(config.profiles.contains_key("laptop"));
+    assert_eq!(config.path(), config_file.path());
+}
+
+fn load_config(content: &str) -> Config {
+    let temp = TempDir::new().unwrap();
+    let config_file = temp.child("config.toml");
+    config_file.write_str(content).unwrap();
+    Config::load_from_file(config_file.path()).unwrap()
+}
+
+#[test]
+fn test_profiles_keep_file_order() {
+    let config = load_config(
+        r#"
+[profile.zeta]
+[profile.alpha]
+[profile.mid]
+"#,
+    );
+
+    let ids: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+    assert_eq!(ids, ["zeta", "alpha", "mid"]);
+}
+
+#[rstest]
+#[case("", "", "docked-a")]
+#[case("priority = 1", "", "docked-a")]
+#[case("", "priority = 1", "docked-b")]
+#[case("priority = -1", "", "docked-b")]
+#[case("priority = 5", "priority = 5", "docked-a")]
+fn test_find_matching_profile_priority(
+    #[case] priority_a: &str,
+    #[case] priority_b: &str,
+    #[case] expected_profile_name: &str,
+) {
+    let config = load_config(&format!(
+        r#"
+[profile.docked-a]
+{priority_a}
+
+[[profile.docked-a.settings]]
+output = "eDP-1"
+
+[[profile.docked-a.settings]]
+output = "HDMI-*"
+
+[profile.docked-b]
+{priority_b}
+
+[[profile.docked-b.settings]]
+output = "eDP-1"
+
+[[profile.docked-b.settings]]
+output = "Dell*"
+"#
+    ));
+    let connected_outputs = vec![
+        make_output("eDP-1", Some("Laptop"), Some("Screen"), None),
+        make_output("HDMI-A-1", Some("Dell"), Some("Monitor"), None),
+    ];
+
+    for _ in 0..10 {
+        let (profile_id, _, _) = config.find_matching_profile(&connected_outputs).unwrap();
+        assert_eq!(profile_id, expected_profile_name);
+    }
+}
+
+#[rstest]
+#[case("", 2, false)]
+#[case(r#"extra_outputs = "reject""#, 2, false)]
+#[case(r#"extra_outputs = "keep""#, 2, true)]
+#[case(r#"extra_outputs = "off""#, 3, true)]
+#[case(r#"extra_outputs = "auto""#, 1, true)]
+#[case(r#"extra_outputs = "keep""#, 0, false)]
+fn test_find_matching_profile_extra_outputs(
+    #[case] extra_outputs: &str,
+    #[case] connected_count: usize,
+    #[case] should_match: bool,
+) {
+    let config = load_config(&format!(
+        r#"
+[profile.desk]
+{extra_outputs}
+
+[[profile.desk.settings]]
+output = "eDP-1"
+on = true
+"#
+    ));
+    let connected_outputs: Vec<OutputInfo> = [
+        make_output("eDP-1", Some("Laptop"), Some("Screen"), None),
+        make_output("HDMI-A-1", Some("Projector"), Some("Beamer"), None),
+        make_output("HEADLESS-1", None, None, None),
+    ]
+    .into_iter()
+    .take(connected_count)
+    .collect();
+
+    let result = config.find_matching_profile(&connected_outputs);
+
+    assert_eq!(result.is_some(), should_match);
+}
+
+#[test]
+fn test_config_invalid_extra_outputs() {
+    let temp = TempDir::new().unwrap();
+    let config_file = temp.child("config.toml");
+    config_file
+        .write_str("[profile.desk]\nextra_outputs = \"sometimes\"\n")
+        .unwrap();
+
+    assert!(Config::load_from_file(config_file.path()).is_err());
+}
+
+#[test]
+fn test_config_hooks() {
+    let config = load_config(
+        r#"
+[profile.desk]
+pre_exec = ["systemctl --user stop waybar"]
+post_exec = [
+    { command = "systemctl --user start waybar", timeout = 2.5 },
+    { command = "notify-send docked" },
+]
+"#,
+    );
+
+    let desk = &config.profiles["desk"];
+    assert_eq!(
+        desk.pre_exec,
+        vec![Hook::new("systemctl --user stop waybar")]
+    );
+    assert_eq!(
+        desk.post_exec,
+        vec![
+            Hook {
+                command: "systemctl --user start waybar".into(),
+                timeout: std::time::Duration::from_millis(2500),
+            },
+            Hook {
+                command: "notify-send docked".into(),
+                timeout: DEFAULT_HOOK_TIMEOUT,
+            },
+        ]
+    );
+}
+
+#[rstest]
+#[case(r#"pre_exec = [{ command = "true", timeout = -1 }]"#)]
+#[case(r#"pre_exec = [{ command = "true", wait = false }]"#)]
+#[case(r#"post_exec = [{ timeout = 1 }]"#)]
+fn test_config_invalid_hooks(#[case] hooks: &str) {
+    let temp = TempDir::new().unwrap();
+    let config_file = temp.child("config.toml");
+    config_file
+        .write_str(&format!("[profile.desk]\n{hooks}\n"))
+        .unwrap();
+
+    assert!(Config::load_from_file(config_file.path()).is_err());
+}
+
+#[rstest]
+#[case("HDMI-*", "HDMI-A-1", "precise")]
+#[case("HDMI-*", "Dell Inc. U2718Q ABC123456", "precise")]
+#[case("HDMI-*", "ABC123456", "precise")]
+#[case("HDMI-A-1", "Dell Inc. U2718Q ABC123456", "precise")]
+#[case("ABC123456", "Dell Inc. U2718Q ABC123456", "generic")]
+#[case("Dell*", "HDMI-*", "generic")]
+fn test_find_matching_profile_specificity(
+    #[case] generic_pattern: &str,
+    #[case] precise_pattern: &str,
+    #[case] expected_profile_name: &str,
+) {
+    let config = load_config(&format!(
+        r#"
+[profile.generic]
+
+[[profile.generic.settings]]
+output = "eDP-1"
+
+[[profile.generic.settings]]
+output = "{generic_pattern}"
+
+[profile.precise]
+
+[[profile.precise.settings]]
+output = "eDP-1"
+
+[[profile.precise.settings]]
+output = "{precise_pattern}"
+"#
+    ));
+    let connected_outputs = vec![
+        make_output("eDP-1", Some("Laptop"), Some("Screen"), None),
+        make_output(
+            "HDMI-A-1",
+            Some("Dell Inc."),
+            Some("U2718Q"),
+            Some("ABC123456"),
+        ),
+    ];
+
+    let (profile_id, _, _) = config.find_matching_profile(&connected_outputs).unwrap();
+
+    assert_eq!(profile_id, expected_profile_name);
+}
+
+#[test]
+fn test_find_matching_profile_priority_beats_specificity() {
+    let config = load_config(
+        r#"
+[profile.precise]
+
+[[profile.precise.settings]]
+output = "ABC123456"
+
+[profile.generic]
+priority = 1
+
+[[profile.generic.settings]]
+output = "*"
+"#,
+    );
+    let connected_outputs = vec![make_output(
+        "HDMI-A-1",
+        Some("Dell Inc."),
+        Some("U2718Q"),
+        Some("ABC123456"),
+    )];
+
+    let (profile_id, _, _) = config.find_matching_profile(&connected_outputs).unwrap();
+
+    assert_eq!(profile_id, "generic");
+}
+
+#[test]
+fn test_matching_profiles_order() {
+    let config = load_config(
+        r#"
+[profile.generic]
+
+[[profile.generic.settings]]
+output = "*"
+
+[profile.precise]
+
+[[profile.precise.settings]]
+output = "ABC123456"
+
+[profile.other]
+
+[[profile.other.settings]]
+output = "DEF456"
+
+[profile.mirror]
+priority = -1
+
+[[profile.mirror.settings]]
+output = "HDMI-*"
+
+[profile.also-generic]
+
+[[profile.also-generic.settings]]
+output = "Dell*"
+"#,
+    );
+    let connected_outputs = vec![dell("HDMI-A-1", "ABC123456")];
+
+    let ids: Vec<&str> = config
+        .matching_profiles(&connected_outputs)
+        .into_iter()
+        .map(|(profile_id, _, _)| profile_id)
+        .collect();
+
+    assert_eq!(ids, ["precise", "generic", "also-generic", "mirror"]);
+}
+
+fn dell(name: &str, serial: &str) -> OutputInfo {
+    make_output(name, Some("Dell Inc."), Some("U2718Q"), Some(serial))
+}
+
+#[rstest]
+#[case(&["Dell*", "Dell Inc. U2718Q ABC123"], true)]
+#[case(&["Dell Inc. U2718Q ABC123", "Dell*"], true)]
+#[case(&["*", "Dell*", "DEF456"], true)]
+#[case(&["DEF456", "*", "Dell*"], true)]
+#[case(&["Dell*", "*", "ABC123"], true)]
+#[case(&["ABC123", "Dell Inc. U2718Q ABC123"], false)]
+#[case(&["Dell*", "Dell*", "Dell*"], false)]
+fn test_find_matching_profile_assignment_order(
+    #[case] patterns: &[&str],
+    #[case] should_match: bool,
+) {
+    let settings: String = patterns
+        .iter()
+        .map(|pattern| format!("[[profile.desk.settings]]\noutput = \"{pattern}\"\n\n"))
+        .collect();
+    let config = load_config(&format!(
+        "[profile.desk]\nextra_outputs = \"keep\"\n\n{settings}"
+    ));
+    let connected_outputs = vec![
+        dell("DP-1", "ABC123"),
+        dell("DP-2", "DEF456"),
+        make_output("eDP-1", Some("Laptop"), Some("Screen"), None),
+    ];
+
+    let result = config.find_matching_profile(&connected_outputs);
+
+    assert_eq!(result.is_some(), should_match);
+}
+
+#[test]
+fn test_find_matching_profile_assigns_specific_pattern() {
+    let config = load_config(
+        r#"
+[profile.desk]
+
+[[profile.desk.settings]]
+output = "Dell*"
+
+[[profile.desk.settings]]
+output = "Dell Inc. U2718Q ABC123"
+"#,
+    );
+    let connected_outputs = vec![dell("DP-1", "ABC123"), dell("DP-2", "DEF456")];
+
+    let (_, _, name_map) = config.find_matching_profile(&connected_outputs).unwrap();
+
+    assert_eq!(name_map["Dell Inc. U2718Q ABC123"], "DP-1");
+    assert_eq!(name_map["Dell*"], "DP-2");
+}
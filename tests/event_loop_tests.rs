@@ -1,17 +1,28 @@
-use auto_wlr_randr::config::{Config, Profile};
+use auto_wlr_randr::backend::MockBackend;
+use auto_wlr_randr::config::{
+    Config, ExtraOutputs, FALLBACK_PROFILE_ID, Fallback, FallbackLayout, Hook, OutputSetting,
+    Profile,
+};
 use auto_wlr_randr::event_loop::handle_command;
-use auto_wlr_randr::ipc::Command;
+use auto_wlr_randr::ipc::{Command, SwitchOptions};
 use auto_wlr_randr::output::OutputInfo;
 use auto_wlr_randr::wayland::WaylandState;
-use std::collections::HashMap;
+use indexmap::IndexMap;
+use rstest::rstest;
+use std::time::Duration;
 
 fn create_test_state() -> WaylandState {
-    let mut profiles = HashMap::new();
+    create_test_state_with_backend(MockBackend::default())
+}
+
+fn create_test_state_with_backend(backend: MockBackend) -> WaylandState {
+    let mut profiles = IndexMap::new();
     profiles.insert(
         "test".to_string(),
         Profile {
             exec: vec![],
             settings: vec![],
+            ..Default::default()
         },
     );
 
@@ -27,13 +38,14 @@ fn create_test_state() -> WaylandState {
 
     config.profiles = profiles;
 
-    let mut state = WaylandState::new(config);
+    let mut state = WaylandState::new(config, Box::new(backend));
 
     state.outputs = vec![OutputInfo {
         name: "TEST-1".to_string(),
         make: Some("Test Inc.".to_string()),
         model: Some("TestModel".to_string()),
         serial: None,
+        ..Default::default()
     }];
 
     state
@@ -57,7 +69,10 @@ fn test_handle_command_status() {
 fn test_handle_command_switch_valid() {
     let mut state = create_test_state();
 
-    let result = handle_command(Command::Switch("test".to_string()), &mut state);
+    let result = handle_command(
+        Command::Switch("test".to_string(), SwitchOptions::default()),
+        &mut state,
+    );
 
     assert!(result.is_ok());
     assert_eq!(state.active_profile_id, Some("test".to_string()));
@@ -67,7 +82,904 @@ fn test_handle_command_switch_valid() {
 fn test_handle_command_switch_invalid() {
     let mut state = create_test_state();
 
-    let result = handle_command(Command::Switch("nonexistent".to_string()), &mut state);
+    let result = handle_command(
+        Command::Switch("nonexistent".to_string(), SwitchOptions::default()),
+        &mut state,
+    );
+
+    assert!(result.is_err());
+}
+
+fn make_output(name: &str, model: &str) -> OutputInfo {
+    OutputInfo {
+        name: name.to_string(),
+        make: Some("Test Inc.".to_string()),
+        model: Some(model.to_string()),
+        serial: None,
+        ..Default::default()
+    }
+}
+
+fn make_setting(output: &str, on: bool) -> OutputSetting {
+    OutputSetting {
+        output: output.into(),
+        alias: None,
+        on: Some(on),
+        mode: None,
+        preferred: false,
+        pos: None,
+        left_of: None,
+        right_of: None,
+        above: None,
+        below: None,
+        transform: None,
+        scale: None,
+        adaptive_sync: None,
+    }
+}
+
+fn create_hotplug_state(backend: MockBackend) -> WaylandState {
+    let mut state = create_test_state_with_backend(backend);
+    state.config.profiles.clear();
+    state.config.profiles.insert(
+        "laptop".to_string(),
+        Profile {
+            exec: vec![],
+            settings: vec![make_setting("eDP-1", true)],
+            ..Default::default()
+        },
+    );
+    state.config.profiles.insert(
+        "docked".to_string(),
+        Profile {
+            exec: vec![],
+            settings: vec![
+                make_setting("eDP-1", false),
+                make_setting("Test Inc. Monitor", true),
+            ],
+            ..Default::default()
+        },
+    );
+    state
+}
+
+#[test]
+fn test_refresh_outputs_follows_hotplug() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+
+    backend.set_outputs(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+
+    let applied = backend.applied();
+    assert_eq!(applied.len(), 2);
+    assert_eq!(applied[1].name_map["Test Inc. Monitor"], "DP-3");
+
+    let outputs = backend.connected_outputs();
+    assert!(!outputs[0].enabled);
+    assert!(outputs[1].enabled);
+
+    backend.set_outputs(vec![make_output("HDMI-A-1", "Projector")]);
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id, None);
+    assert_eq!(backend.applied().len(), 2);
+}
+
+#[test]
+fn test_backend_changes_schedule_refresh() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.refresh_outputs();
+    assert!(!state.check_backend_changes());
+
+    // e.g. a head connected but left disabled, which gets no wl_output
+    let mut monitor = make_output("DP-3", "Monitor");
+    monitor.enabled = false;
+    backend.set_outputs(vec![make_output("eDP-1", "Panel"), monitor]);
+    assert!(state.check_backend_changes());
+
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+}
+
+#[test]
+fn test_handle_command_switch_applies_settings() {
+    let backend = MockBackend::new(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+
+    let result = handle_command(
+        Command::Switch("laptop".to_string(), SwitchOptions::default()),
+        &mut state,
+    );
+
+    assert!(result.is_ok());
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+    assert!(backend.connected_outputs()[0].enabled);
+}
+
+#[rstest]
+#[case::pinned(false)]
+#[case::sticky(true)]
+fn test_switched_profile_is_pinned(#[case] sticky: bool) {
+    let backend = MockBackend::new(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+
+    let options = SwitchOptions {
+        sticky,
+        ..Default::default()
+    };
+    handle_command(Command::Switch("laptop".into(), options), &mut state).unwrap();
+    assert_eq!(state.pin.as_ref().unwrap().sticky, sticky);
+
+    // the same outputs again, e.g. a monitor waking up
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+
+    let status = handle_command(Command::Status, &mut state).unwrap();
+    let status: serde_json::Value = serde_json::from_str(&status).unwrap();
+    assert_eq!(status["pinned"]["profile"], "laptop");
+    assert_eq!(
+        status["pinned"]["outputs"],
+        serde_json::json!(["DP-3", "eDP-1"])
+    );
+
+    backend.set_outputs(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-4", "Monitor"),
+    ]);
+    state.refresh_outputs();
+    if sticky {
+        assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+        assert!(state.pin.is_some());
+    } else {
+        assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+        assert_eq!(state.pin, None);
+    }
+}
+
+#[test]
+fn test_sticky_pin_not_applied_to_other_outputs() {
+    let backend = MockBackend::new(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+
+    let options = SwitchOptions {
+        sticky: true,
+        ..Default::default()
+    };
+    handle_command(Command::Switch("docked".into(), options), &mut state).unwrap();
+    let applied = backend.applied().len();
+
+    backend.set_outputs(vec![make_output("eDP-1", "Panel")]);
+    state.refresh_outputs();
+
+    assert_eq!(backend.applied().len(), applied);
+    assert!(state.pin.is_some());
+    let last_apply = state.last_apply.clone().unwrap();
+    assert!(!last_apply.success);
+    assert!(
+        last_apply
+            .error
+            .unwrap()
+            .contains("doesn't match the connected outputs")
+    );
+
+    backend.set_outputs(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-4", "Monitor"),
+    ]);
+    state.refresh_outputs();
+    assert_eq!(state.name_map["Test Inc. Monitor"], "DP-4");
+    assert!(state.last_apply.clone().unwrap().success);
+}
+
+#[test]
+fn test_cycle_matching_profiles() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.config.profiles.insert(
+        "laptop-scaled".to_string(),
+        Profile {
+            priority: -1,
+            settings: vec![make_setting("eDP-1", true)],
+            ..Default::default()
+        },
+    );
+    state.config.profiles.insert(
+        "laptop-rotated".to_string(),
+        Profile {
+            settings: vec![make_setting("eDP-*", true)],
+            ..Default::default()
+        },
+    );
 
+    let result = handle_command(Command::Next, &mut state);
     assert!(result.is_err());
+
+    state.refresh_outputs();
+    assert_eq!(
+        state.matching_profile_ids(),
+        ["laptop", "laptop-rotated", "laptop-scaled"]
+    );
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+
+    let mut visited = Vec::new();
+    for _ in 0..3 {
+        handle_command(Command::Next, &mut state).unwrap();
+        visited.push(state.active_profile_id.clone().unwrap());
+    }
+    assert_eq!(visited, ["laptop-rotated", "laptop-scaled", "laptop"]);
+
+    handle_command(Command::Prev, &mut state).unwrap();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop-scaled"));
+    // pinned like a switch, so it survives outputs being reported again
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop-scaled"));
+}
+
+#[test]
+fn test_remembered_profile_preferred() {
+    let docked = vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ];
+    let backend = MockBackend::new(docked.clone());
+    let mut state = create_hotplug_state(backend.clone());
+    state.config.profiles.insert(
+        "mirrored".to_string(),
+        Profile {
+            priority: -1,
+            settings: vec![make_setting("eDP-1", true), make_setting("DP-*", true)],
+            ..Default::default()
+        },
+    );
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+
+    let options = SwitchOptions::default();
+    handle_command(Command::Switch("mirrored".to_string(), options), &mut state).unwrap();
+
+    backend.set_outputs(vec![make_output("eDP-1", "Panel")]);
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+
+    // reported under another name, still the same monitor
+    backend.set_outputs(vec![
+        make_output("DP-4", "Monitor"),
+        make_output("eDP-1", "Panel"),
+    ]);
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("mirrored"));
+}
+
+#[test]
+fn test_lock_suspends_matching() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+
+    handle_command(Command::Lock, &mut state).unwrap();
+    backend.set_outputs(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+    assert_eq!(backend.applied().len(), 1);
+
+    let status = handle_command(Command::Status, &mut state).unwrap();
+    let status: serde_json::Value = serde_json::from_str(&status).unwrap();
+    assert_eq!(status["locked"], true);
+
+    // switching by hand still works while locked
+    let options = SwitchOptions::default();
+    handle_command(Command::Switch("docked".into(), options), &mut state).unwrap();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+
+    handle_command(Command::Lock, &mut state).unwrap();
+    assert!(state.locked);
+    handle_command(Command::Unlock, &mut state).unwrap();
+    assert!(!state.locked);
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+}
+
+fn confirm(timeout: Duration) -> SwitchOptions {
+    SwitchOptions {
+        confirm: Some(timeout),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_switch_reverts_unless_confirmed() {
+    let backend = MockBackend::new(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+
+    let timeout = Duration::from_millis(50);
+    handle_command(
+        Command::Switch("laptop".into(), confirm(timeout)),
+        &mut state,
+    )
+    .unwrap();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+    assert_eq!(state.awaiting_confirmation(), Some("laptop"));
+    assert!(state.confirmation_timeout().unwrap() <= timeout);
+
+    // nothing happens before the deadline
+    state.revert_unconfirmed();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+
+    std::thread::sleep(timeout);
+    state.revert_unconfirmed();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+    assert_eq!(state.awaiting_confirmation(), None);
+    assert!(!backend.connected_outputs()[0].enabled);
+
+    handle_command(
+        Command::Switch("laptop".into(), confirm(timeout)),
+        &mut state,
+    )
+    .unwrap();
+    let result = handle_command(Command::Confirm, &mut state).unwrap();
+    assert_eq!(result, "Profile 'laptop' confirmed.");
+    assert_eq!(state.confirmation_timeout(), None);
+
+    std::thread::sleep(timeout);
+    state.revert_unconfirmed();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+    assert!(handle_command(Command::Confirm, &mut state).is_err());
+}
+
+#[test]
+fn test_unconfirmed_switch_restores_outputs_without_previous_profile() {
+    let backend = MockBackend::new(vec![
+        OutputInfo {
+            enabled: true,
+            ..make_output("eDP-1", "Panel")
+        },
+        make_output("DP-3", "Monitor"),
+    ]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.config.profiles.shift_remove("docked");
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id, None);
+
+    let laptop = state.config.profiles.get_mut("laptop").unwrap();
+    laptop.settings = vec![make_setting("eDP-1", false)];
+    let timeout = Duration::from_millis(10);
+    handle_command(
+        Command::Switch("laptop".into(), confirm(timeout)),
+        &mut state,
+    )
+    .unwrap();
+    assert!(!backend.connected_outputs()[0].enabled);
+
+    std::thread::sleep(timeout);
+    state.revert_unconfirmed();
+    assert_eq!(state.active_profile_id, None);
+    assert!(backend.connected_outputs()[0].enabled);
+}
+
+#[test]
+fn test_unconfirmed_switch_after_hotplug_does_not_restore_stale_profile() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.config.profiles.insert(
+        "bright".to_string(),
+        Profile {
+            settings: vec![make_setting("eDP-1", true)],
+            extra_outputs: ExtraOutputs::Keep,
+            ..Default::default()
+        },
+    );
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+
+    let timeout = Duration::from_millis(10);
+    let options = SwitchOptions {
+        sticky: true,
+        ..confirm(timeout)
+    };
+    handle_command(Command::Switch("bright".into(), options), &mut state).unwrap();
+    backend.set_outputs(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("bright"));
+
+    // "laptop" doesn't match with the monitor plugged in, the profile matching now is used
+    std::thread::sleep(timeout);
+    state.revert_unconfirmed();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+    assert_eq!(state.awaiting_confirmation(), None);
+}
+
+#[test]
+fn test_handle_command_reload_reapplies() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.refresh_outputs();
+    assert_eq!(backend.applied().len(), 1);
+
+    state.evaluate_profiles(false);
+    assert_eq!(backend.applied().len(), 1);
+
+    state.evaluate_profiles(true);
+    assert_eq!(backend.applied().len(), 2);
+}
+
+#[test]
+fn test_handle_command_test_profile() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+
+    let result = handle_command(Command::Test("laptop".to_string()), &mut state);
+    assert!(result.is_ok());
+    assert!(backend.applied().is_empty());
+    assert_eq!(state.active_profile_id, None);
+
+    backend.set_fail_test(true);
+    let result = handle_command(Command::Test("laptop".to_string()), &mut state);
+    assert!(result.is_err());
+
+    let result = handle_command(Command::Test("nonexistent".to_string()), &mut state);
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_rejected_profile_is_not_applied() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.config.test_before_apply = true;
+    backend.set_fail_test(true);
+
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id, None);
+    assert!(backend.applied().is_empty());
+
+    let result = handle_command(
+        Command::Switch("laptop".to_string(), SwitchOptions::default()),
+        &mut state,
+    );
+    assert!(result.is_err());
+
+    backend.set_fail_test(false);
+    // failed profiles are retried for the same outputs once the config is reloaded
+    state.evaluate_profiles(true);
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+    assert_eq!(backend.applied().len(), 1);
+}
+
+#[test]
+fn test_extra_outputs_are_turned_off() {
+    let backend = MockBackend::new(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("HDMI-A-1", "Projector"),
+    ]);
+    let mut state = create_hotplug_state(backend.clone());
+    let laptop = state.config.profiles.get_mut("laptop").unwrap();
+    laptop.extra_outputs = ExtraOutputs::Off;
+
+    state.refresh_outputs();
+
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+    let outputs = backend.connected_outputs();
+    assert!(outputs[0].enabled);
+    assert!(!outputs[1].enabled);
+}
+
+#[test]
+fn test_fallback_applied_when_nothing_matches() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    state.config.fallback = Some(Fallback {
+        layout: FallbackLayout::Auto,
+        ..Default::default()
+    });
+
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+
+    backend.set_outputs(vec![
+        make_output("HDMI-A-1", "Projector"),
+        make_output("DP-1", "Conference Room"),
+    ]);
+    state.refresh_outputs();
+    assert_eq!(
+        state.active_profile_id.as_deref(),
+        Some(FALLBACK_PROFILE_ID)
+    );
+    assert!(backend.connected_outputs().iter().all(|o| o.enabled));
+
+    // another unknown screen is laid out again even though the fallback stays active
+    backend.set_outputs(vec![make_output("HDMI-A-1", "Projector")]);
+    state.refresh_outputs();
+    assert_eq!(
+        state.active_profile_id.as_deref(),
+        Some(FALLBACK_PROFILE_ID)
+    );
+    assert_eq!(backend.applied().len(), 3);
+
+    backend.set_outputs(vec![]);
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id, None);
+}
+
+#[test]
+fn test_failed_apply_is_reported() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    backend.set_fail_apply(true);
+
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id, None);
+    let last_apply = state.last_apply.clone().unwrap();
+    assert_eq!(last_apply.profile, "laptop");
+    assert!(!last_apply.success);
+    assert!(last_apply.error.unwrap().contains("rejected"));
+
+    let status = handle_command(Command::Status, &mut state).unwrap();
+    let status: serde_json::Value = serde_json::from_str(&status).unwrap();
+    assert_eq!(status["last_apply"]["success"], false);
+
+    backend.set_fail_apply(false);
+    // failed profiles are retried for the same outputs once the config is reloaded
+    state.evaluate_profiles(true);
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+    let last_apply = state.last_apply.clone().unwrap();
+    assert!(last_apply.success);
+    assert_eq!(last_apply.error, None);
+}
+
+#[rstest]
+#[case::rejected(true)]
+#[case::silently_ignored(false)]
+fn test_partially_applied_profile_is_rolled_back(#[case] rejected: bool) {
+    let outputs = vec![
+        OutputInfo {
+            enabled: true,
+            ..make_output("eDP-1", "Panel")
+        },
+        make_output("DP-3", "Monitor"),
+    ];
+    let backend = MockBackend::new(outputs.clone());
+    let mut state = create_hotplug_state(backend.clone());
+    if rejected {
+        backend.set_rejected_outputs(&["DP-3"]);
+    } else {
+        backend.set_ignored_outputs(&["DP-3"]);
+    }
+
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id, None);
+    let error = state.last_apply.clone().unwrap().error.unwrap();
+    if rejected {
+        assert!(error.contains("rejected outputs DP-3"), "{error}");
+    } else {
+        assert!(error.contains("was not applied fully"), "{error}");
+        assert!(error.contains("DP-3 (enabled)"), "{error}");
+    }
+
+    // eDP-1 was turned off before DP-3 failed, restoring turns only it back on
+    let connected = backend.connected_outputs();
+    assert!(connected[0].enabled);
+    assert!(!connected[1].enabled);
+    let applied = backend.applied();
+    assert_eq!(applied.len(), 2);
+    assert_eq!(applied[1].profile.settings.len(), 1);
+    assert_eq!(applied[1].profile.settings[0].output, "eDP-1");
+
+    backend.set_rejected_outputs(&[]);
+    backend.set_ignored_outputs(&[]);
+    // not retried for the same outputs until the config is reloaded
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id, None);
+    state.evaluate_profiles(true);
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+}
+
+#[test]
+fn test_rollback_does_not_retry_failed_profile() {
+    let backend = MockBackend::new(vec![
+        OutputInfo {
+            enabled: true,
+            ..make_output("eDP-1", "Panel")
+        },
+        make_output("DP-3", "Monitor"),
+    ]);
+    let mut state = create_hotplug_state(backend.clone());
+    backend.set_rejected_outputs(&["DP-3"]);
+
+    state.refresh_outputs();
+    assert_eq!(backend.applied().len(), 2);
+
+    // what process_events does, the rollback is reported as a change of the outputs
+    for _ in 0..5 {
+        if !state.check_backend_changes() {
+            break;
+        }
+        state.refresh_outputs();
+    }
+    assert!(!state.check_backend_changes());
+    assert_eq!(backend.applied().len(), 2);
+
+    // a different set of outputs is worth another try
+    backend.set_rejected_outputs(&[]);
+    backend.set_outputs(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-4", "Monitor"),
+    ]);
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+}
+
+#[test]
+fn test_exec_children_are_reaped() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend);
+    let laptop = state.config.profiles.get_mut("laptop").unwrap();
+    laptop.exec = vec!["true".into(), "echo oops >&2; exit 3".into()];
+
+    state.refresh_outputs();
+    assert_eq!(state.children.running(), 2);
+
+    for _ in 0..100 {
+        state.children.reap();
+        if state.children.running() == 0 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(state.children.running(), 0);
+}
+
+#[test]
+fn test_hooks_run_in_order() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let log = temp.path().join("hooks.log");
+    let log = log.display();
+
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    let laptop = state.config.profiles.get_mut("laptop").unwrap();
+    laptop.pre_exec = vec![
+        Hook::new(format!("sleep 0.1; echo stop >> {log}")),
+        Hook::new(format!("echo pre >> {log}; exit 1")),
+    ];
+    laptop.post_exec = vec![Hook::new(format!("echo start >> {log}"))];
+
+    backend.set_fail_apply(true);
+    state.refresh_outputs();
+    assert_eq!(
+        std::fs::read_to_string(log.to_string()).unwrap(),
+        "stop\npre\n"
+    );
+
+    backend.set_fail_apply(false);
+    // failed profiles are retried for the same outputs once the config is reloaded
+    state.evaluate_profiles(true);
+    assert_eq!(
+        std::fs::read_to_string(log.to_string()).unwrap(),
+        "stop\npre\nstop\npre\nstart\n"
+    );
+}
+
+#[test]
+fn test_hook_timeout_kills() {
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend);
+    let laptop = state.config.profiles.get_mut("laptop").unwrap();
+    laptop.pre_exec = vec![Hook {
+        command: "sleep 10".into(),
+        timeout: std::time::Duration::from_millis(100),
+    }];
+
+    let start = std::time::Instant::now();
+    state.refresh_outputs();
+
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+}
+
+#[test]
+fn test_hook_with_lots_of_stderr_finishes() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let log = temp.path().join("hooks.log");
+
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend);
+    let laptop = state.config.profiles.get_mut("laptop").unwrap();
+    // far more than a pipe holds before the writer blocks
+    laptop.pre_exec = vec![Hook {
+        command: format!(
+            "head -c 1000000 /dev/zero | tr '\\0' x >&2; echo done > {}",
+            log.display()
+        ),
+        timeout: std::time::Duration::from_secs(5),
+    }];
+
+    let start = std::time::Instant::now();
+    state.refresh_outputs();
+
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), "done\n");
+}
+
+#[test]
+fn test_hooks_get_profile_context() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let log = temp.path().join("hooks.log");
+
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    for profile in state.config.profiles.values_mut() {
+        profile.post_exec = vec![Hook::new(format!(
+            "echo \"$AUTO_WLR_RANDR_PREVIOUS_PROFILE -> $AUTO_WLR_RANDR_PROFILE\" {{Test Inc. Monitor.name}} > {0}; \
+             echo \"$AUTO_WLR_RANDR_NAME_MAP\" >> {0}; echo \"$AUTO_WLR_RANDR_OUTPUTS\" >> {0}",
+            log.display()
+        ))];
+    }
+
+    state.refresh_outputs();
+    let written = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = written.lines().collect();
+    assert_eq!(lines[0], " -> laptop {Test Inc. Monitor.name}");
+
+    backend.set_outputs(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    state.refresh_outputs();
+    let written = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = written.lines().collect();
+    assert_eq!(lines[0], "laptop -> docked DP-3");
+
+    let name_map: std::collections::HashMap<String, String> =
+        serde_json::from_str(lines[1]).unwrap();
+    assert_eq!(name_map["Test Inc. Monitor"], "DP-3");
+
+    let outputs: Vec<OutputInfo> = serde_json::from_str(lines[2]).unwrap();
+    assert_eq!(outputs.len(), 2);
+    assert!(!outputs[0].enabled);
+    assert!(outputs[1].enabled);
+}
+
+#[test]
+fn test_on_deactivate_runs_before_next_post_exec() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let log = temp.path().join("hooks.log");
+    let log_path = log.display();
+
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    for profile in state.config.profiles.values_mut() {
+        profile.post_exec = vec![Hook::new(format!(
+            "echo \"enter $AUTO_WLR_RANDR_PROFILE\" >> {log_path}"
+        ))];
+        profile.on_deactivate = vec![Hook::new(format!(
+            "echo \"leave $AUTO_WLR_RANDR_PROFILE\" >> {log_path}"
+        ))];
+    }
+
+    state.refresh_outputs();
+    // reapplying the active profile doesn't leave it
+    state.evaluate_profiles(true);
+
+    backend.set_outputs(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    state.refresh_outputs();
+
+    backend.set_outputs(vec![make_output("HDMI-A-1", "Projector")]);
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id, None);
+
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        "enter laptop\nenter laptop\nleave laptop\nenter docked\nleave docked\n"
+    );
+}
+
+#[rstest]
+#[case::unknown_relative_target("relative")]
+#[case::missing_mode("mode")]
+#[case::rejected_by_compositor("test")]
+#[case::failed_apply("apply")]
+fn test_failing_profile_keeps_active_one(#[case] failure: &str) {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let log = temp.path().join("hooks.log");
+
+    let backend = MockBackend::new(vec![make_output("eDP-1", "Panel")]);
+    let mut state = create_hotplug_state(backend.clone());
+    let laptop = state.config.profiles.get_mut("laptop").unwrap();
+    laptop.on_deactivate = vec![Hook::new(format!(
+        "echo \"leave $AUTO_WLR_RANDR_PROFILE\" >> {}",
+        log.display()
+    ))];
+    let docked = state.config.profiles.get_mut("docked").unwrap();
+    match failure {
+        "relative" => docked.settings[1].left_of = Some("HDMI-A-1".into()),
+        "mode" => docked.settings[1].mode = Some("1234x567".into()),
+        _ => {}
+    }
+
+    state.refresh_outputs();
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+    match failure {
+        "test" => {
+            state.config.test_before_apply = true;
+            backend.set_fail_test(true);
+        }
+        "apply" => backend.set_fail_apply(true),
+        _ => {}
+    }
+
+    backend.set_outputs(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    state.refresh_outputs();
+
+    assert_eq!(state.active_profile_id.as_deref(), Some("laptop"));
+    assert!(!state.last_apply.as_ref().unwrap().success);
+    assert!(!log.exists());
+    assert_eq!(backend.applied().len(), 1);
+}
+
+#[test]
+fn test_relative_target_by_pattern() {
+    let backend = MockBackend::new(vec![
+        make_output("eDP-1", "Panel"),
+        make_output("DP-3", "Monitor"),
+    ]);
+    let mut state = create_hotplug_state(backend.clone());
+    let docked = state.config.profiles.get_mut("docked").unwrap();
+    docked.settings[0].on = Some(true);
+    docked.settings[0].left_of = Some("Test Inc. Monitor".into());
+
+    state.refresh_outputs();
+
+    assert_eq!(state.active_profile_id.as_deref(), Some("docked"));
+    // placed by the daemon, the outputs have no modes so they're 0x0
+    let applied = backend.applied();
+    assert_eq!(applied[0].profile.settings[0].left_of, None);
+    assert_eq!(applied[0].profile.settings[0].pos.as_deref(), Some("0,0"));
+
+    let docked = state.config.profiles.get_mut("docked").unwrap();
+    docked.settings[0].left_of = Some("HDMI-A-1".into());
+    state.evaluate_profiles(true);
+
+    let last_apply = state.last_apply.clone().unwrap();
+    assert!(!last_apply.success);
+    assert!(last_apply.error.unwrap().contains("'HDMI-A-1'"));
+    assert_eq!(backend.applied().len(), 1);
 }
@@ -0,0 +1,69 @@
+use assert_fs::TempDir;
+use assert_fs::prelude::*;
+use auto_wlr_randr::history::{ProfileHistory, fingerprint};
+use auto_wlr_randr::output::OutputInfo;
+
+fn make_output(name: &str, model: &str, serial: Option<&str>) -> OutputInfo {
+    OutputInfo {
+        name: name.to_string(),
+        make: Some("Test Inc.".to_string()),
+        model: Some(model.to_string()),
+        serial: serial.map(str::to_string),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_fingerprint_ignores_order_and_names() {
+    let outputs = [
+        make_output("eDP-1", "Panel", None),
+        make_output("DP-3", "Monitor", Some("ABC")),
+    ];
+    let reordered = [
+        make_output("DP-5", "Monitor", Some("ABC")),
+        make_output("eDP-1", "Panel", None),
+    ];
+    assert_eq!(fingerprint(&outputs), fingerprint(&reordered));
+
+    let other_serial = [
+        make_output("eDP-1", "Panel", None),
+        make_output("DP-3", "Monitor", Some("XYZ")),
+    ];
+    assert_ne!(fingerprint(&outputs), fingerprint(&other_serial));
+}
+
+#[test]
+fn test_history_survives_reload() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.child("state/auto-wlr-randr/last-profiles.json");
+    let docked = [
+        make_output("eDP-1", "Panel", None),
+        make_output("DP-3", "Monitor", Some("ABC")),
+    ];
+    let laptop = [make_output("eDP-1", "Panel", None)];
+
+    let mut history = ProfileHistory::load(path.path());
+    assert_eq!(history.get(&docked), None);
+    history.record(&docked, "presentation").unwrap();
+    history.record(&laptop, "laptop").unwrap();
+
+    let history = ProfileHistory::load(path.path());
+    assert_eq!(history.get(&docked), Some("presentation"));
+    assert_eq!(history.get(&laptop), Some("laptop"));
+}
+
+#[test]
+fn test_corrupt_history_starts_empty() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.child("last-profiles.json");
+    path.write_str("{ not json").unwrap();
+    let laptop = [make_output("eDP-1", "Panel", None)];
+
+    let mut history = ProfileHistory::load(path.path());
+    assert_eq!(history.get(&laptop), None);
+    history.record(&laptop, "laptop").unwrap();
+    assert_eq!(
+        ProfileHistory::load(path.path()).get(&laptop),
+        Some("laptop")
+    );
+}
@@ -1,5 +1,6 @@
-use auto_wlr_randr::ipc::{Command, ensure_socket_dir_exists, get_socket_path};
+use auto_wlr_randr::ipc::{Command, SwitchOptions, ensure_socket_dir_exists, get_socket_path};
 use serde_json::{from_str, to_string};
+use std::time::Duration;
 
 #[test]
 fn test_command_serialization() {
@@ -21,16 +22,35 @@ fn test_command_serialization() {
         _ => panic!("Expected Command::Status"),
     }
 
-    let switch_cmd = Command::Switch("test-profile".to_string());
+    let options = SwitchOptions {
+        confirm: Some(Duration::from_secs(15)),
+        sticky: true,
+    };
+    let switch_cmd = Command::Switch("test-profile".to_string(), options.clone());
     let json = to_string(&switch_cmd).unwrap();
-    assert_eq!(json, r#"{"Switch":"test-profile"}"#);
+    assert_eq!(
+        json,
+        r#"{"Switch":["test-profile",{"confirm":{"secs":15,"nanos":0},"sticky":true}]}"#
+    );
     let deserialized: Command = from_str(&json).unwrap();
     match deserialized {
-        Command::Switch(name) => {
+        Command::Switch(name, deserialized_options) => {
             assert_eq!(name, "test-profile");
+            assert_eq!(deserialized_options, options);
         }
         _ => panic!("Expected Command::Switch"),
     }
+
+    let test_cmd = Command::Test("test-profile".to_string());
+    let json = to_string(&test_cmd).unwrap();
+    assert_eq!(json, r#"{"Test":"test-profile"}"#);
+    let deserialized: Command = from_str(&json).unwrap();
+    match deserialized {
+        Command::Test(name) => {
+            assert_eq!(name, "test-profile");
+        }
+        _ => panic!("Expected Command::Test"),
+    }
 }
 
 #[test]
@@ -0,0 +1,80 @@
+use assert_fs::TempDir;
+use assert_fs::prelude::*;
+use auto_wlr_randr::watcher::{ConfigWatcher, DEBOUNCE};
+use std::thread::sleep;
+use std::time::Duration;
+
+fn wait_for_change(watcher: &mut ConfigWatcher) -> bool {
+    // inotify events are queued by the kernel right away, give them a moment anyway
+    sleep(Duration::from_millis(20));
+    watcher.handle_events().unwrap();
+    if watcher.timeout().is_none() {
+        return false;
+    }
+
+    sleep(DEBOUNCE);
+    watcher.take_due()
+}
+
+#[test]
+fn test_watcher_in_place_write() {
+    let temp = TempDir::new().unwrap();
+    let config_file = temp.child("config.toml");
+    config_file.write_str("[profile.laptop]\n").unwrap();
+
+    let mut watcher = ConfigWatcher::new(config_file.path()).unwrap();
+    assert!(watcher.timeout().is_none());
+
+    config_file
+        .write_str("[profile.laptop]\n\n[profile.docked]\n")
+        .unwrap();
+
+    assert!(wait_for_change(&mut watcher));
+    assert!(watcher.timeout().is_none());
+}
+
+#[test]
+fn test_watcher_atomic_replace() {
+    let temp = TempDir::new().unwrap();
+    let config_file = temp.child("config.toml");
+    config_file.write_str("[profile.laptop]\n").unwrap();
+
+    let mut watcher = ConfigWatcher::new(config_file.path()).unwrap();
+
+    for _ in 0..2 {
+        let swap = temp.child(".config.toml.swp");
+        swap.write_str("[profile.docked]\n").unwrap();
+        std::fs::rename(swap.path(), config_file.path()).unwrap();
+
+        assert!(wait_for_change(&mut watcher));
+    }
+}
+
+#[test]
+fn test_watcher_ignores_other_files() {
+    let temp = TempDir::new().unwrap();
+    let config_file = temp.child("config.toml");
+    config_file.write_str("[profile.laptop]\n").unwrap();
+
+    let mut watcher = ConfigWatcher::new(config_file.path()).unwrap();
+
+    temp.child("other.toml").write_str("foo").unwrap();
+
+    assert!(!wait_for_change(&mut watcher));
+}
+
+#[test]
+fn test_watcher_debounce() {
+    let temp = TempDir::new().unwrap();
+    let config_file = temp.child("config.toml");
+    config_file.write_str("[profile.laptop]\n").unwrap();
+
+    let mut watcher = ConfigWatcher::new(config_file.path()).unwrap();
+
+    config_file.write_str("[profile.docked]\n").unwrap();
+    sleep(Duration::from_millis(20));
+    watcher.handle_events().unwrap();
+
+    assert!(watcher.timeout().unwrap() > Duration::ZERO);
+    assert!(!watcher.take_due());
+}